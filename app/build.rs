@@ -0,0 +1,30 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Boards that define where their storage region ends, see the `board-*`
+/// features.
+const BOARDS: &[&str] = &["nrf52840-dk", "nrf52840-dongle"];
+
+/// Puts `storage.x` and the selected board's storage region on the linker
+/// search path and links them into the firmware after `memory.x` from the
+/// HAL.
+fn main() {
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::copy("storage.x", out.join("storage.x")).unwrap();
+    println!("cargo:rerun-if-changed=storage.x");
+    for board in BOARDS {
+        println!("cargo:rerun-if-changed=storage-{board}.x");
+    }
+
+    // Without a board the build fails in the board map.
+    let Some(board) = BOARDS.iter().find(|board| {
+        let feature = board.to_uppercase().replace('-', "_");
+        env::var_os(format!("CARGO_FEATURE_BOARD_{feature}")).is_some()
+    }) else {
+        return;
+    };
+    fs::copy(format!("storage-{board}.x"), out.join("storage-board.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rustc-link-arg-bins=-Tstorage.x");
+}
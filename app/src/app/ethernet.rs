@@ -1,22 +1,29 @@
 use crate::drivers;
+use crate::events::{self, NetworkEvent};
 use crate::subsys;
 use drivers::api::mono::MonoDriver;
+use drivers::api::watchdog::{WatchdogDriver, WatchdogTask};
 use smoltcp::{
     iface::{Interface, SocketHandle, SocketSet},
     socket::dhcpv4,
     time::Instant,
     wire::{IpCidr, Ipv4Address, Ipv4Cidr},
 };
-use subsys::usb::{class_cdc_ncm_eth::CdcNcmEthClass, HalUsbBus, SubsysUsbDevice};
+use subsys::usb::{class_cdc_ncm_eth::CdcNcmEthClass, SubsysUsbDevice};
 
 pub fn idle(
-    usb_dev: &impl SubsysUsbDevice<HalUsbBus<'static>, CdcNcmEthClass>,
+    usb_dev: &mut impl SubsysUsbDevice<drivers::UsbBus, CdcNcmEthClass<drivers::UsbBus>>,
     dhcp_handle: &SocketHandle,
     interface: &mut Interface,
     sockets: &mut SocketSet<'static>,
     mono: &impl MonoDriver<Instant = drivers::Instant, Duration = drivers::Duration>,
+    watchdog: Option<&impl WatchdogDriver>,
 ) -> ! {
     loop {
+        if let Some(watchdog) = watchdog {
+            watchdog.check_in(WatchdogTask::Idle);
+        }
+
         // cortex_m::asm::wfe();
 
         usb_dev.poll(&mut |subsys_class| {
@@ -55,11 +62,17 @@ fn dhcp_poll(iface: &mut Interface, socket: &mut dhcpv4::Socket) {
             for (i, s) in config.dns_servers.iter().enumerate() {
                 defmt::info!("     DNS server {}:    {}", i, s);
             }
+
+            events::NETWORK.publish(NetworkEvent::Configured {
+                address: config.address,
+                router: config.router,
+            });
         }
         Some(dhcpv4::Event::Deconfigured) => {
             defmt::info!("dhcp: DHCP deconfigured");
             set_ipv4_addr(iface, Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0));
             iface.routes_mut().remove_default_ipv4_route();
+            events::NETWORK.publish(NetworkEvent::Deconfigured);
         }
     }
 }
@@ -3,38 +3,53 @@
 #![deny(warnings)]
 #![deny(unsafe_code)]
 
-use co2_sensor::{drivers, subsys};
+use co2_sensor::{buffers, drivers, events, subsys};
+use static_cell::StaticCell;
+use subsys::usb::class_cdc_ncm_eth::CdcNcmEthClass;
+
+type EthernetClass = CdcNcmEthClass<drivers::UsbBus>;
+
+static ETHERNET: StaticCell<EthernetClass> = StaticCell::new();
 
 #[rtic::app(device = drivers::pac, dispatchers = [SWI0_EGU0])]
 mod app {
     use super::*;
 
-    use device_nrf::NrfUsbDevice;
+    use device::HalUsbDevice;
+    use drivers::api::adc::*;
     use drivers::api::gpio::*;
     use drivers::api::mono::*;
+    use drivers::api::pwm::*;
     use drivers::api::rng::*;
+    use drivers::api::watchdog::*;
     use fugit::ExtU32;
     use smoltcp::{
-        iface::{Config, Interface, SocketHandle, SocketSet, SocketStorage},
+        iface::{Config, Interface, SocketHandle, SocketSet},
         socket::dhcpv4,
         time::{Duration, Instant},
         wire::{DhcpOption, EthernetAddress, HardwareAddress, Ipv4Address, Ipv4Cidr},
     };
-    use static_cell::StaticCell;
-    use subsys::usb::{self, *};
+    use subsys::usb::*;
 
     const HOST_NAME: &[u8] = b"co2-sensor-gateway";
     const DEVICE_MAC_ADDR: [u8; 6] = [0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC];
+    const CO2_ALARM_PPM: u16 = 2000;
+    const ALARM_TONE_HZ: u32 = 2000;
 
     #[shared]
     struct Shared {
-        mono: drivers::MonoDriver,
-        gpio: drivers::GpioDriver,
+        mono: Option<drivers::MonoDriver>,
+        gpio: Option<drivers::GpioDriver>,
+        watchdog: Option<drivers::WatchdogDriver>,
     }
 
     #[local]
     struct Local {
-        usb_dev: NrfUsbDevice,
+        network: Option<Network>,
+    }
+
+    struct Network {
+        usb_dev: HalUsbDevice<drivers::UsbBus, EthernetClass>,
         dhcp_handle: SocketHandle,
         interface: Interface,
         sockets: SocketSet<'static>,
@@ -53,14 +68,100 @@ mod app {
 
         let mut drivers = drivers::init(cx.device);
 
-        let ethernet = usb::class_cdc_ncm_eth::CdcNcmEthClass::new(drivers.usb);
+        if let Some(task) = drivers
+            .watchdog
+            .as_ref()
+            .and_then(|watchdog| watchdog.last_failure())
+        {
+            defmt::error!("Reset by the watchdog, {} did not check in.", task);
+        }
+
+        if let Some(adc) = drivers.adc.as_ref() {
+            let vdd = adc.read(AdcChannel::Vdd, Oversample::X16);
+            let vbus = adc.read(AdcChannel::VddhDiv5, Oversample::X16);
+            defmt::info!(
+                "Supply: VDD {=u32} mV, VDDH {=u32} mV",
+                vdd.millivolts(),
+                vbus.millivolts()
+            );
+            let battery = adc.read(AdcChannel::Battery, Oversample::X16).millivolts();
+            defmt::info!(
+                "Battery: {=u32} mV, {=u8} %",
+                battery,
+                battery_percent(battery, LIPO_CURVE)
+            );
+        }
+
+        let network = match (drivers.usb, drivers.mono.as_ref()) {
+            (Some(usb_driver), Some(mono)) => {
+                let random_seed = match drivers.rng.as_mut() {
+                    Some(rng) => rng.next_u64(),
+                    None => {
+                        defmt::warn!("No RNG, using a fixed random seed.");
+                        0
+                    }
+                };
+                Some(init_network(usb_driver, random_seed, mono))
+            }
+            (None, _) => {
+                defmt::warn!("No USB, networking disabled.");
+                None
+            }
+            (_, None) => {
+                defmt::warn!("No monotonic timer, networking disabled.");
+                None
+            }
+        };
+
+        // Schedule the blinking and button tasks
+        let mut blinking = false;
+        if drivers.mono.is_none() {
+            defmt::warn!("No monotonic timer, timed tasks disabled.");
+        } else {
+            if let Some(gpio) = drivers.gpio.as_ref() {
+                blinking = blink::spawn().is_ok();
+                if let Ok(button) = gpio.input_pin(GpioInputPin::Button) {
+                    button_events::spawn(button).ok();
+                }
+            }
+
+            if let Some(pwm) = drivers.pwm {
+                co2_indicator::spawn(pwm).ok();
+            }
+        }
+
+        // Only tasks that run can check in.
+        if let Some(watchdog) = drivers.watchdog.as_ref() {
+            if blinking {
+                watchdog.start(&WatchdogTask::ALL);
+            } else {
+                watchdog.start(&[WatchdogTask::Idle]);
+            }
+        }
+
+        (
+            Shared {
+                mono: drivers.mono,
+                gpio: drivers.gpio,
+                watchdog: drivers.watchdog,
+            },
+            Local { network },
+        )
+    }
+
+    fn init_network(
+        usb_driver: &'static drivers::UsbDriver,
+        random_seed: u64,
+        mono: &drivers::MonoDriver,
+    ) -> Network {
+        let ethernet = ETHERNET.init(EthernetClass::new(usb_driver));
 
         let mut interface_config =
             Config::new(HardwareAddress::Ethernet(EthernetAddress(DEVICE_MAC_ADDR)));
-        interface_config.random_seed = drivers.rng.next_u64();
+        interface_config.random_seed = random_seed;
 
         let now = Instant::from_micros(
-            i64::try_from(drivers.mono.now().duration_since_epoch().to_micros()).unwrap(),
+            i64::try_from(mono.now().duration_since_epoch().to_micros()).unwrap(),
         );
         let mut interface = Interface::new(interface_config, &mut ethernet.usb_class, now);
         interface.update_ip_addrs(|ip_addrs| {
@@ -80,64 +181,180 @@ mod app {
             data: HOST_NAME,
         }]);
 
-        static SOCKETS: StaticCell<[SocketStorage<'static>; 2]> = StaticCell::new();
-        let sockets = SOCKETS.init(Default::default());
+        let sockets = buffers::SOCKETS.alloc(Default::default()).unwrap().leak();
         let mut sockets = SocketSet::new(&mut sockets[..]);
         let dhcp_handle = sockets.add(dhcp_socket);
 
-        let usb_dev = usb::device_nrf::NrfUsbDevice::new(drivers.usb, ethernet);
-
-        // Schedule the blinking task
-        blink::spawn().ok();
+        let usb_dev = HalUsbDevice::new(usb_driver, ethernet);
 
-        (
-            Shared {
-                mono: drivers.mono,
-                gpio: drivers.gpio,
-            },
-            Local {
-                usb_dev,
-                dhcp_handle,
-                interface,
-                sockets,
-            },
-        )
+        Network {
+            usb_dev,
+            dhcp_handle,
+            interface,
+            sockets,
+        }
     }
 
-    #[task(shared = [gpio, &mono], priority=1)]
+    #[task(shared = [gpio, &mono, &watchdog], priority=1)]
     async fn blink(cx: blink::Context) {
-        let mono = cx.shared.mono;
+        let Some(mono) = cx.shared.mono else {
+            return;
+        };
+        let watchdog = cx.shared.watchdog;
         let mut gpio = cx.shared.gpio;
+        let mut network_events = events::NETWORK.subscribe().unwrap();
+        let mut usb_events = events::USB.subscribe().unwrap();
 
+        // Blink fast until the network is configured, stay dark while USB
+        // is suspended.
+        let mut configured = false;
+        let mut suspended = false;
         let mut next_tick = mono.now();
         let mut blink_on = false;
         loop {
+            while let Some(event) = network_events.try_next() {
+                configured = matches!(event, events::NetworkEvent::Configured { .. });
+            }
+            while let Some(event) = usb_events.try_next() {
+                match event {
+                    events::UsbEvent::Suspended => suspended = true,
+                    events::UsbEvent::Resumed => suspended = false,
+                    events::UsbEvent::Reset => configured = false,
+                    events::UsbEvent::Configured => {}
+                }
+            }
+
             gpio.lock(|gpio| {
-                if blink_on {
-                    gpio.output_pin(GpioOutputPin::LED).set_high().unwrap();
-                } else {
-                    gpio.output_pin(GpioOutputPin::LED).set_low().unwrap();
+                if gpio.is_none() {
+                    return;
                 }
+                drivers::GpioDriver::with_output_pin_mut(GpioOutputPin::LED, |led| {
+                    if blink_on && !suspended {
+                        led.set_high().unwrap();
+                    } else {
+                        led.set_low().unwrap();
+                    }
+                });
             });
 
             blink_on = !blink_on;
+            if let Some(watchdog) = watchdog {
+                watchdog.check_in(WatchdogTask::Blink);
+            }
 
-            next_tick += 1000.millis();
+            next_tick += if configured {
+                1000.millis()
+            } else {
+                250.millis()
+            };
             mono.delay_until(next_tick).await;
         }
     }
 
-    #[idle(local = [usb_dev, dhcp_handle, interface, sockets], shared = [&mono])]
+    #[task(shared = [&mono], priority = 1)]
+    async fn button_events(
+        cx: button_events::Context,
+        pin: <drivers::GpioDriver as GpioDriver>::InputPin,
+    ) {
+        let Some(mono) = cx.shared.mono else {
+            return;
+        };
+        let mut button = subsys::button::Button::new(
+            pin,
+            mono,
+            subsys::button::ButtonTimings {
+                debounce: drivers::Duration::millis(20),
+                long_press: drivers::Duration::millis(800),
+                double_press_gap: drivers::Duration::millis(300),
+            },
+        );
+        loop {
+            let event = button.next_event().await;
+            defmt::info!("button: {}", event);
+            events::BUTTON.publish(event);
+        }
+    }
+
+    /// Shows the CO2 level in colour and sounds an alarm when it is too high.
+    #[task(shared = [&mono], priority = 1)]
+    async fn co2_indicator(cx: co2_indicator::Context, pwm: drivers::PwmDriver) {
+        let Some(mono) = cx.shared.mono else {
+            return;
+        };
+        let mut measurements = events::MEASUREMENT.subscribe().unwrap();
+        loop {
+            let events::MeasurementEvent { co2_ppm } = measurements.next().await;
+            let color = match co2_ppm {
+                0..800 => Rgb::new(0, 255, 0),
+                800..1400 => Rgb::new(255, 160, 0),
+                _ => Rgb::new(255, 0, 0),
+            };
+            pwm.fade_to(color.dimmed(64), 1000).await;
+            if co2_ppm >= CO2_ALARM_PPM {
+                pwm.tone(ALARM_TONE_HZ);
+                mono.delay(drivers::Duration::millis(200)).await;
+                pwm.tone(0);
+            }
+        }
+    }
+
+    #[task(binds = PWM0, priority = 2)]
+    fn pwm_leds(_: pwm_leds::Context) {
+        <drivers::PwmDriver as PwmDriver>::on_interrupt();
+    }
+
+    #[task(binds = GPIOTE, priority = 2)]
+    fn gpiote(_: gpiote::Context) {
+        <drivers::GpioDriver as GpioDriver>::on_interrupt();
+    }
+
+    #[task(binds = WDT, priority = 3)]
+    fn wdt(_: wdt::Context) {
+        <drivers::WatchdogDriver as WatchdogDriver>::on_interrupt();
+    }
+
+    #[task(binds = SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0, priority = 2)]
+    fn i2c(_: i2c::Context) {
+        <drivers::I2cDriver as drivers::api::i2c::I2cDriver>::on_interrupt();
+    }
+
+    #[task(binds = SPIM2_SPIS2_SPI2, priority = 2)]
+    fn spi(_: spi::Context) {
+        <drivers::SpiDriver as drivers::api::spi::SpiDriver>::on_interrupt();
+    }
+
+    #[task(binds = UARTE0_UART0, priority = 2)]
+    fn uart(_: uart::Context) {
+        <drivers::UartDriver as drivers::api::uart::UartDriver>::on_interrupt();
+    }
+
+    #[task(binds = TIMER1, priority = 2)]
+    fn uart_idle(_: uart_idle::Context) {
+        <drivers::UartDriver as drivers::api::uart::UartDriver>::on_interrupt();
+    }
+
+    #[idle(local = [network], shared = [&mono, &watchdog])]
     fn idle(cx: idle::Context) -> ! {
-        let idle::LocalResources {
-            usb_dev,
-            dhcp_handle,
-            interface,
-            sockets,
-            ..
-        } = cx.local;
-        let mono = cx.shared.mono;
+        // The network is only set up if the monotonic timer is running.
+        let (
+            Some(Network {
+                usb_dev,
+                dhcp_handle,
+                interface,
+                sockets,
+            }),
+            Some(mono),
+        ) = (cx.local.network, cx.shared.mono)
+        else {
+            loop {
+                if let Some(watchdog) = cx.shared.watchdog {
+                    watchdog.check_in(WatchdogTask::Idle);
+                }
+                cortex_m::asm::wfi();
+            }
+        };
+        let watchdog = cx.shared.watchdog.as_ref();
 
-        co2_sensor::app::ethernet::idle(usb_dev, dhcp_handle, interface, sockets, mono)
+        co2_sensor::app::ethernet::idle(usb_dev, dhcp_handle, interface, sockets, mono, watchdog)
     }
 }
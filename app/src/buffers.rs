@@ -0,0 +1,36 @@
+//! Statically reserved buffers. All pools live in RAM so that their buffers
+//! can be handed to EasyDMA.
+
+use di::pool::{Pool, PoolInfo};
+use smoltcp::iface::SocketStorage;
+
+pub const ETHERNET_BUFFER_SIZE: usize = 2048;
+pub const NUM_SOCKETS: usize = 2;
+pub const SPI_DMA_BUFFER_SIZE: usize = 256;
+pub const UART_RX_BUFFER_SIZE: usize = 32;
+pub const UART_TX_BUFFER_SIZE: usize = 64;
+pub const PWM_FADE_STEPS: usize = 32;
+
+/// Ethernet in and out buffers of the CDC-NCM class.
+pub static ETHERNET: Pool<[u8; ETHERNET_BUFFER_SIZE], 2> = Pool::new("ETHERNET");
+
+/// Socket storage of the network stack.
+pub static SOCKETS: Pool<[SocketStorage<'static>; NUM_SOCKETS], 1> = Pool::new("SOCKETS");
+
+/// Bounce buffer for SPI writes from flash, which EasyDMA cannot read.
+pub static SPI_DMA: Pool<[u8; SPI_DMA_BUFFER_SIZE], 1> = Pool::new("SPI_DMA");
+
+/// UART reception alternates between two buffers.
+pub static UART_RX: Pool<[u8; UART_RX_BUFFER_SIZE], 2> = Pool::new("UART_RX");
+
+pub static UART_TX: Pool<[u8; UART_TX_BUFFER_SIZE], 1> = Pool::new("UART_TX");
+
+/// LED fade sequence, one compare value per PWM channel and step.
+pub static PWM_FADE: Pool<[[u16; 4]; PWM_FADE_STEPS], 1> = Pool::new("PWM_FADE");
+
+pub static PWM_TONE: Pool<[u16; 1], 1> = Pool::new("PWM_TONE");
+
+/// All pools, reported at boot.
+pub const POOLS: &[&dyn PoolInfo] = &[
+    &ETHERNET, &SOCKETS, &SPI_DMA, &UART_RX, &UART_TX, &PWM_FADE, &PWM_TONE,
+];
@@ -1,55 +1,99 @@
 pub mod api;
 
+#[cfg(feature = "platform-nrf52840")]
+mod platform_nrf;
+#[cfg(feature = "platform-nrf52840")]
 mod resources_nrf;
 
+#[cfg(feature = "platform-nrf52840")]
+mod adc_nrf;
+#[cfg(feature = "platform-nrf52840")]
+mod board_nrf;
+#[cfg(feature = "platform-nrf52840")]
+mod flash_nrf;
+#[cfg(feature = "platform-nrf52840")]
 mod gpio_nrf;
+#[cfg(feature = "platform-nrf52840")]
+mod i2c_nrf;
+#[cfg(feature = "platform-nrf52840")]
 mod log_defmt_rtt;
+#[cfg(feature = "platform-nrf52840")]
 mod mono_nrf_rtic;
+#[cfg(feature = "platform-nrf52840")]
 mod osc_nrf;
+#[cfg(feature = "platform-nrf52840")]
 mod power_nrf;
+#[cfg(feature = "platform-nrf52840")]
+mod pwm_nrf;
+#[cfg(feature = "platform-nrf52840")]
 mod rng_nrf;
+#[cfg(feature = "platform-nrf52840")]
 mod soc_cortex_m;
+#[cfg(feature = "platform-nrf52840")]
+mod spi_nrf;
+#[cfg(feature = "platform-nrf52840")]
+mod uart_nrf;
+#[cfg(feature = "platform-nrf52840")]
 mod usb_nrf;
+#[cfg(feature = "platform-nrf52840")]
+mod watchdog_nrf;
 
-use api::osc::*;
-use gpio_nrf::NrfGpioDriverState;
-use mono_nrf_rtic::NrfRticMonoDriver;
-pub use mono_nrf_rtic::{Duration, Instant};
-use osc_nrf::{NrfHighAccOscillatorDriver, NrfOscillatorsDriver, NrfSleepOscillatorDriver};
+use api::platform::Platform;
+
+#[cfg(not(feature = "platform-nrf52840"))]
+compile_error!("select a platform, usually through one of the `board-*` features");
+
+#[cfg(feature = "platform-nrf52840")]
+pub use platform_nrf::{registry, NrfPlatform};
+#[cfg(feature = "platform-nrf52840")]
 pub use resources_nrf::pac;
-use rng_nrf::NrfRngDriverState;
-use usb_nrf::NrfUsbDriver;
-
-pub struct Drivers<'a> {
-    pub osc: &'a NrfOscillatorsDriver<NrfSleepOscillatorDriver, NrfHighAccOscillatorDriver>,
-    pub rng: NrfRngDriverState,
-    pub mono: NrfRticMonoDriver,
-    pub gpio: NrfGpioDriverState,
-    pub usb: &'a mut NrfUsbDriver,
+
+/// The platform the firmware is built for, selected by a `platform-*`
+/// feature. Code outside of `drivers` only refers to drivers through the
+/// aliases below and the `api` traits.
+#[cfg(feature = "platform-nrf52840")]
+pub type Target = NrfPlatform;
+
+/// Initializes the drivers of the target platform.
+pub fn init(peripherals: <Target as Platform>::Peripherals) -> Drivers {
+    Target::init(peripherals)
 }
 
-pub type MonoDriver = NrfRticMonoDriver;
-pub type GpioDriver = NrfGpioDriverState;
-pub type UsbDriver = NrfUsbDriver;
-
-/// Instantiate drivers that take ownership of the peripherals.
-pub fn init<'a>(peripherals: pac::Peripherals) -> Drivers<'a> {
-    let resources = resources_nrf::init(peripherals);
-
-    soc_cortex_m::init().unwrap();
-    log_defmt_rtt::init().unwrap();
-    power_nrf::init(resources.power).unwrap();
-    let osc = osc_nrf::init(resources.osc).unwrap();
-    let rng = rng_nrf::init(resources.rng).unwrap();
-    let mono = mono_nrf_rtic::init(resources.monotonic, osc).unwrap();
-    let gpio = gpio_nrf::init(resources.gpio).unwrap();
-    let usb = usb_nrf::init(resources.usb, osc).unwrap();
-
-    Drivers {
-        osc,
-        rng,
-        mono,
-        gpio,
-        usb,
-    }
+/// Logs the state of the drivers of the target platform.
+pub fn dump_status() {
+    Target::dump_status()
 }
+
+/// Drivers that came up during init. Drivers that failed to initialize are
+/// `None` so that the application can run in a degraded mode. Details are
+/// available from the `registry()`.
+pub struct Drivers<P: Platform = Target> {
+    pub rng: Option<P::Rng>,
+    pub mono: Option<P::Mono>,
+    pub gpio: Option<P::Gpio>,
+    pub i2c: Option<P::I2c>,
+    pub spi: Option<P::Spi>,
+    pub uart: Option<P::Uart>,
+    pub adc: Option<P::Adc>,
+    pub pwm: Option<P::Pwm>,
+    pub watchdog: Option<P::Watchdog>,
+    pub flash: Option<P::Flash>,
+    pub usb: Option<&'static P::Usb>,
+}
+
+pub type RngDriver = <Target as Platform>::Rng;
+pub type MonoDriver = <Target as Platform>::Mono;
+pub type GpioDriver = <Target as Platform>::Gpio;
+pub type I2cDriver = <Target as Platform>::I2c;
+pub type SpiDriver = <Target as Platform>::Spi;
+pub type UartDriver = <Target as Platform>::Uart;
+pub type AdcDriver = <Target as Platform>::Adc;
+pub type PwmDriver = <Target as Platform>::Pwm;
+pub type WatchdogDriver = <Target as Platform>::Watchdog;
+pub type FlashDriver = <Target as Platform>::Flash;
+pub type UsbDriver = <Target as Platform>::Usb;
+pub type UsbBus = <Target as Platform>::UsbBus;
+pub type CeilingLock<const CEILING: u8> = <Target as Platform>::CeilingLock<CEILING>;
+
+pub type Instant = <MonoDriver as api::mono::MonoDriver>::Instant;
+pub type Duration = <MonoDriver as api::mono::MonoDriver>::Duration;
@@ -0,0 +1,153 @@
+use super::api::adc::*;
+use super::board_nrf as board;
+use super::power_nrf::NrfPowerDriver;
+use super::resources_nrf::NrfCeilingLock;
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+use di::resources::{Composite, Owner};
+use di::singleton::{Singleton, Teardown, TryDefault};
+use di::{InitError, WithDependency};
+use di_macros::{driver, Dependency, Singleton};
+use nrf52840_hal::pac::SAADC;
+
+/// 12 bit single-ended conversions.
+const MAX_RAW: u16 = 4096;
+
+/// Gain 1/6 with the internal 0.6 V reference.
+const FULL_SCALE_MV: u32 = 3600;
+
+#[derive(Dependency)]
+#[dependency(owner = NrfAdcState)]
+struct NrfAdcResources {
+    saadc: SAADC,
+    battery: board::Battery,
+}
+
+pub struct NrfAdcState {
+    saadc: SAADC,
+    _battery: board::Battery,
+}
+
+impl TryDefault for NrfAdcState {
+    fn try_default() -> Result<Self, InitError> {
+        Self::with_dependency(|NrfAdcResources { saadc, battery }| {
+            saadc.enable.write(|w| w.enable().enabled());
+            saadc.resolution.write(|w| w.val()._12bit());
+            saadc.ch[0].config.write(|w| {
+                w.refsel()
+                    .internal()
+                    .gain()
+                    .gain1_6()
+                    .tacq()
+                    ._10us()
+                    .mode()
+                    .se()
+                    .resp()
+                    .bypass()
+                    .resn()
+                    .bypass()
+                    // Oversampled reads need a single SAMPLE task only.
+                    .burst()
+                    .enabled()
+            });
+            Self {
+                saadc,
+                _battery: battery,
+            }
+        })
+    }
+}
+
+impl Teardown for NrfAdcState {
+    fn teardown(self) {
+        self.saadc.enable.write(|w| w.enable().disabled());
+        Self::return_dependency(
+            NrfAdcResources {
+                saadc: self.saadc,
+                battery: self._battery,
+            }
+            .into_parts(),
+        );
+    }
+}
+
+impl NrfAdcState {
+    fn sample(&mut self, channel: AdcChannel, oversample: Oversample) -> i16 {
+        let saadc = &self.saadc;
+        saadc.ch[0].pselp.write(|w| match channel {
+            AdcChannel::Vdd => w.pselp().vdd(),
+            AdcChannel::VddhDiv5 => w.pselp().vddhdiv5(),
+            AdcChannel::Battery => w.pselp().analog_input0(),
+        });
+        saadc.oversample.write(|w| match oversample {
+            Oversample::None => w.oversample().bypass(),
+            Oversample::X4 => w.oversample().over4x(),
+            Oversample::X16 => w.oversample().over16x(),
+            Oversample::X64 => w.oversample().over64x(),
+        });
+
+        let mut result: i16 = 0;
+        // SAFETY: The result lives on the stack until the conversion ended.
+        saadc
+            .result
+            .ptr
+            .write(|w| unsafe { w.ptr().bits(&mut result as *mut i16 as u32) });
+        saadc.result.maxcnt.write(|w| unsafe { w.maxcnt().bits(1) });
+
+        saadc.events_started.reset();
+        saadc.tasks_start.write(|w| unsafe { w.bits(1) });
+        while saadc.events_started.read().bits() == 0 {}
+        saadc.events_started.reset();
+
+        saadc.events_end.reset();
+        saadc.tasks_sample.write(|w| unsafe { w.bits(1) });
+        while saadc.events_end.read().bits() == 0 {}
+        saadc.events_end.reset();
+        compiler_fence(Ordering::SeqCst);
+
+        // SAFETY: Written by EasyDMA behind the compiler's back.
+        unsafe { ptr::read_volatile(&result) }
+    }
+
+    fn calibrate(&mut self) {
+        let saadc = &self.saadc;
+        saadc.events_calibratedone.reset();
+        saadc.tasks_calibrateoffset.write(|w| unsafe { w.bits(1) });
+        while saadc.events_calibratedone.read().bits() == 0 {}
+        saadc.events_calibratedone.reset();
+    }
+}
+
+#[derive(Singleton)]
+#[singleton(content = NrfAdcState, lock = NrfCeilingLock<1>)]
+struct NrfAdcDriverState;
+
+#[driver(state = NrfAdcDriverState, init = Self::calibrate_on_init, depends_on(NrfPowerDriver))]
+pub struct NrfAdcDriver;
+
+impl NrfAdcDriver {
+    fn calibrate_on_init(&self) -> Result<(), InitError> {
+        self.calibrate();
+        Ok(())
+    }
+}
+
+impl AdcDriver for NrfAdcDriver {
+    fn read(&self, channel: AdcChannel, oversample: Oversample) -> AdcSample {
+        let raw = NrfAdcDriverState::with_ref_mut(|state| state.sample(channel, oversample));
+        let full_scale_mv = match channel {
+            AdcChannel::Vdd => FULL_SCALE_MV,
+            AdcChannel::VddhDiv5 => FULL_SCALE_MV * 5,
+            AdcChannel::Battery => FULL_SCALE_MV * board::BATTERY_DIVIDER,
+        };
+        AdcSample {
+            raw,
+            full_scale_mv,
+            max_raw: MAX_RAW,
+        }
+    }
+
+    fn calibrate(&self) {
+        NrfAdcDriverState::with_ref_mut(NrfAdcState::calibrate)
+    }
+}
@@ -1,61 +1,125 @@
+pub mod adc;
+pub mod flash;
 pub mod gpio;
+pub mod i2c;
 pub mod log;
 pub mod mono;
 pub mod osc;
+pub mod platform;
 pub mod power;
+pub mod pwm;
 pub mod rng;
 pub mod soc;
+pub mod spi;
+pub mod uart;
 pub mod usb;
+pub mod watchdog;
 
 use core::error::Error;
 use core::fmt;
 use core::fmt::Debug;
-use core::sync::atomic::{AtomicBool, Ordering};
-use di::singleton::SingletonHolderImpl;
-use di::Initialized;
 
-#[derive(Debug, Clone, Copy)]
-pub struct ApiError(&'static str);
+use di::singleton::{Locked, NotInitialized};
+use di::InitError;
+
+pub use di::driver::Driver;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ApiError {
+    Timeout,
+    HfxoNotStarted,
+    UsbdNotPowered,
+    I2cBusStuck,
+    StorageNotAligned,
+    /// An async task holds the driver.
+    Busy,
+    /// Another handle already talks to the device.
+    DeviceInUse,
+}
 impl Error for ApiError {}
 impl fmt::Display for ApiError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ApiError: {}.", self.0)
+        let message = match self {
+            ApiError::Timeout => "timeout",
+            ApiError::HfxoNotStarted => "HFXO failed to start",
+            ApiError::UsbdNotPowered => "USBD not powered",
+            ApiError::I2cBusStuck => "I2C bus stuck",
+            ApiError::StorageNotAligned => "storage region not page aligned",
+            ApiError::Busy => "driver held by an async task",
+            ApiError::DeviceInUse => "device handle already taken",
+        };
+        write!(f, "ApiError: {message}.")
     }
 }
-pub(super) static TIMEOUT: ApiError = ApiError("timeout");
-
-pub trait Driver: Initialized {}
-
-pub type DriverStateHolder<State> = SingletonHolderImpl<State>;
-
-pub struct InitState(AtomicBool);
-
-impl Initialized for InitState {
-    fn init(&self) {
-        self.0.store(true, Ordering::Release)
+impl From<ApiError> for InitError {
+    fn from(err: ApiError) -> Self {
+        // The constant variants are promoted to statics.
+        InitError::Driver(match err {
+            ApiError::Timeout => &ApiError::Timeout,
+            ApiError::HfxoNotStarted => &ApiError::HfxoNotStarted,
+            ApiError::UsbdNotPowered => &ApiError::UsbdNotPowered,
+            ApiError::I2cBusStuck => &ApiError::I2cBusStuck,
+            ApiError::StorageNotAligned => &ApiError::StorageNotAligned,
+            ApiError::Busy => &ApiError::Busy,
+            ApiError::DeviceInUse => &ApiError::DeviceInUse,
+        })
     }
+}
 
-    fn is_initialized(&self) -> bool {
-        self.0.load(Ordering::Acquire)
+impl From<Locked> for ApiError {
+    fn from(_: Locked) -> Self {
+        ApiError::Busy
     }
 }
 
-#[derive(Default)]
-pub struct StatelessDriver;
+/// Error of a device on a shared bus. Blocking transactions report `Busy`
+/// instead of waiting while an async task holds the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusError<E> {
+    Bus(E),
+    Busy,
+    /// The I2C handle was used for another device than its own.
+    WrongAddress,
+    /// The driver was deinitialized while an async transaction waited for
+    /// the bus.
+    NotInitialized,
+    /// The hardware cannot run the transaction, e.g. adjacent reads in an
+    /// async I2C transaction.
+    Unsupported,
+}
+
+impl<E> From<Locked> for BusError<E> {
+    fn from(_: Locked) -> Self {
+        BusError::Busy
+    }
+}
 
-impl StatelessDriver {
-    fn init_state() -> &'static InitState {
-        static STATE: InitState = InitState(AtomicBool::new(false));
-        &STATE
+impl<E> From<NotInitialized> for BusError<E> {
+    fn from(_: NotInitialized) -> Self {
+        BusError::NotInitialized
     }
 }
 
-impl Initialized for StatelessDriver {
-    fn init(&self) {
-        Self::init_state().ensure_is_initialized();
+impl<E: embedded_hal::i2c::Error> embedded_hal::i2c::Error for BusError<E> {
+    fn kind(&self) -> embedded_hal::i2c::ErrorKind {
+        match self {
+            BusError::Bus(err) => err.kind(),
+            BusError::Busy
+            | BusError::WrongAddress
+            | BusError::NotInitialized
+            | BusError::Unsupported => embedded_hal::i2c::ErrorKind::Other,
+        }
     }
+}
 
-    fn is_initialized(&self) -> bool {
-        Self::init_state().is_initialized()
+impl<E: embedded_hal::spi::Error> embedded_hal::spi::Error for BusError<E> {
+    fn kind(&self) -> embedded_hal::spi::ErrorKind {
+        match self {
+            BusError::Bus(err) => err.kind(),
+            BusError::Busy
+            | BusError::WrongAddress
+            | BusError::NotInitialized
+            | BusError::Unsupported => embedded_hal::spi::ErrorKind::Other,
+        }
     }
 }
@@ -0,0 +1,45 @@
+use super::Driver;
+
+pub use di::battery::{battery_percent, LIPO_CURVE};
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum AdcChannel {
+    /// Supply voltage of the SoC.
+    Vdd,
+    /// High voltage supply, e.g. USB VBUS, divided by five.
+    VddhDiv5,
+    /// Battery voltage on an analog input, behind the board's divider.
+    Battery,
+}
+
+/// Number of samples averaged in hardware per read.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Oversample {
+    None,
+    X4,
+    X16,
+    X64,
+}
+
+#[derive(Clone, Copy, defmt::Format)]
+pub struct AdcSample {
+    pub raw: i16,
+    /// Voltage at the channel input that corresponds to `max_raw`.
+    pub full_scale_mv: u32,
+    pub max_raw: u16,
+}
+
+impl AdcSample {
+    pub fn millivolts(&self) -> u32 {
+        self.raw.max(0) as u32 * self.full_scale_mv / self.max_raw as u32
+    }
+}
+
+pub trait AdcDriver: Driver {
+    /// Single-shot read.
+    fn read(&self, channel: AdcChannel, oversample: Oversample) -> AdcSample;
+
+    /// Calibrates the offset, e.g. after the temperature changed by more
+    /// than 10 °C.
+    fn calibrate(&self);
+}
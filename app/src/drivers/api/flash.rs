@@ -0,0 +1,11 @@
+use super::Driver;
+use embedded_storage::nor_flash::NorFlash;
+use embedded_storage_async::nor_flash::NorFlash as AsyncNorFlash;
+
+pub trait FlashDriver: Driver {
+    type Storage: NorFlash + AsyncNorFlash;
+
+    /// Handle of the flash region that is reserved for persistent data.
+    /// Offsets are relative to the start of the region.
+    fn storage(&self) -> Self::Storage;
+}
@@ -1,12 +1,21 @@
-use super::Driver;
-use embedded_hal::digital::{Error, OutputPin};
+use super::{ApiError, Driver};
+use embedded_hal::digital::{Error, InputPin, OutputPin};
+use embedded_hal_async::digital::Wait;
 
 pub enum GpioOutputPin {
     LED,
+    SensorEnable,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum GpioInputPin {
+    Button,
+    SensorReady,
 }
 
 pub trait GpioDriver: Driver {
     type GpioError: Error;
+    type InputPin: InputPin<Error = Self::GpioError> + Wait<Error = Self::GpioError>;
 
     fn with_output_pin<F>(name: GpioOutputPin, f: F)
     where
@@ -15,4 +24,10 @@ pub trait GpioDriver: Driver {
     fn with_output_pin_mut<F>(name: GpioOutputPin, f: F)
     where
         F: FnOnce(&mut dyn OutputPin<Error = Self::GpioError>);
+
+    /// Hands out the only handle of the pin until it is dropped.
+    fn input_pin(&self, name: GpioInputPin) -> Result<Self::InputPin, ApiError>;
+
+    /// Must be called from the interrupt handler of the pin events.
+    fn on_interrupt();
 }
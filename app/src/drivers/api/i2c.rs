@@ -0,0 +1,27 @@
+use super::{ApiError, Driver};
+use embedded_hal::i2c::I2c;
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum I2cFrequency {
+    K100,
+    K250,
+    K400,
+}
+
+/// I2C bus shared by several devices. Every device gets its own handle,
+/// transactions from different handles are serialized.
+pub trait I2cDriver: Driver {
+    type Device: I2c + AsyncI2c;
+
+    /// Hands out the only handle of the device at the 7-bit `address`. The
+    /// address is free again once the handle is dropped.
+    fn device(&self, address: u8) -> Result<Self::Device, ApiError>;
+
+    fn set_frequency(&self, frequency: I2cFrequency) -> Result<(), ApiError>;
+
+    /// Clocks a device out of an interrupted transfer that holds SDA low.
+    fn recover(&self) -> Result<(), ApiError>;
+
+    fn on_interrupt();
+}
@@ -1,4 +1,4 @@
-use di::token::{Release, SharedToken};
+use di::token::{Counted, Release, SharedToken};
 
 use super::{ApiError, Driver};
 
@@ -6,7 +6,7 @@ pub struct Hertz(pub u32);
 pub struct Ppm(pub u16);
 
 pub trait OscillatorDriver<'a>: Driver {
-    type OscToken: Default + Release + Send;
+    type OscToken: Default + Release + Counted + Send;
 
     fn freq() -> Hertz;
     fn freq_drift() -> Ppm;
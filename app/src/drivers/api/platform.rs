@@ -0,0 +1,46 @@
+use super::adc::AdcDriver;
+use super::flash::FlashDriver;
+use super::gpio::GpioDriver;
+use super::i2c::I2cDriver;
+use super::mono::MonoDriver;
+use super::pwm::PwmDriver;
+use super::rng::RngDriver;
+use super::spi::SpiDriver;
+use super::uart::UartDriver;
+use super::usb::UsbDriver;
+use super::watchdog::WatchdogDriver;
+use crate::drivers::Drivers;
+use di::lock::Lock;
+use rand_core::RngCore;
+use usb_device::bus::UsbBus;
+
+/// Selects the driver implementations of a platform. Applications and
+/// subsystems only depend on these types through the `api` traits, so they
+/// can be built against any platform.
+pub trait Platform: Sized {
+    type Rng: RngDriver + RngCore;
+    type Mono: MonoDriver;
+    type Gpio: GpioDriver;
+    type I2c: I2cDriver;
+    type Spi: SpiDriver;
+    type Uart: UartDriver;
+    type Adc: AdcDriver;
+    type Pwm: PwmDriver;
+    type Watchdog: WatchdogDriver;
+    type Flash: FlashDriver;
+    type UsbBus: UsbBus + 'static;
+    type Usb: UsbDriver<Self::UsbBus> + 'static;
+
+    /// Lock for state that is shared with tasks up to the given priority.
+    type CeilingLock<const CEILING: u8>: Lock + 'static;
+
+    /// Peripherals handed over by the runtime at boot.
+    type Peripherals;
+
+    /// Initializes all drivers of the platform. Drivers that fail to come up
+    /// are missing from the result.
+    fn init(peripherals: Self::Peripherals) -> Drivers<Self>;
+
+    /// Logs the state of the drivers and of shared resources.
+    fn dump_status();
+}
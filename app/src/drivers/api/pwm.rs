@@ -0,0 +1,45 @@
+use super::Driver;
+
+/// Brightness of each colour, zero is off.
+#[derive(Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+    pub const OFF: Rgb = Rgb::new(0, 0, 0);
+
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+
+    /// Scales all colours by `brightness / 255`.
+    pub const fn dimmed(self, brightness: u8) -> Self {
+        const fn scale(value: u8, brightness: u8) -> u8 {
+            (value as u16 * brightness as u16 / u8::MAX as u16) as u8
+        }
+        Self::new(
+            scale(self.red, brightness),
+            scale(self.green, brightness),
+            scale(self.blue, brightness),
+        )
+    }
+}
+
+#[allow(async_fn_in_trait)]
+pub trait PwmDriver: Driver {
+    fn set_color(&self, color: Rgb);
+
+    /// Fades from the current colour to `color`. The fade runs in
+    /// hardware, the future completes when it has finished.
+    async fn fade_to(&self, color: Rgb, duration_ms: u32);
+
+    /// Drives the buzzer with a square wave, a frequency of zero silences
+    /// it. Frequencies outside of the supported range are clamped.
+    fn tone(&self, frequency_hz: u32);
+
+    /// Must be called from the interrupt handler of the LED PWM.
+    fn on_interrupt();
+}
@@ -0,0 +1,15 @@
+use super::Driver;
+use embedded_hal::spi::SpiDevice;
+use embedded_hal_async::spi::SpiDevice as AsyncSpiDevice;
+
+/// SPI bus shared by several devices. Every device gets its own handle that
+/// asserts the device's chip select for the duration of a transaction.
+pub trait SpiDriver: Driver {
+    /// Devices wired to the bus, one per chip select line of the board.
+    type ChipSelect: Copy;
+    type Device: SpiDevice + AsyncSpiDevice;
+
+    fn device(&self, chip_select: Self::ChipSelect) -> Self::Device;
+
+    fn on_interrupt();
+}
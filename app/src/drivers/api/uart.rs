@@ -0,0 +1,20 @@
+use super::Driver;
+use embedded_io::{Read, Write};
+use embedded_io_async::{Read as AsyncRead, Write as AsyncWrite};
+
+#[allow(async_fn_in_trait)]
+pub trait UartPort: Read + Write + AsyncRead + AsyncWrite {
+    /// Waits until the line goes idle and reads what was received until
+    /// then, e.g. a complete Modbus RTU frame.
+    async fn read_frame(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+pub trait UartDriver: Driver {
+    type Port: UartPort;
+
+    fn port(&self) -> Self::Port;
+
+    /// Must be called from the interrupt handlers of the UART and of the
+    /// timer that detects an idle line.
+    fn on_interrupt();
+}
@@ -1,5 +1,9 @@
-use usb_device::bus::UsbBus;
+use super::Driver;
+use usb_device::bus::{UsbBus, UsbBusAllocator};
 
 pub trait UsbDriver<B: UsbBus>: Driver {
-    fn bus(&self) -> &'static B;
+    /// Allocator of the bus, created on first use. Classes and the device
+    /// keep the bus for the rest of the program from then on, so the driver
+    /// refuses to be deinitialized afterwards.
+    fn usb_alloc(&self) -> &'static UsbBusAllocator<B>;
 }
@@ -0,0 +1,28 @@
+use super::Driver;
+
+/// Tasks supervised by the watchdog. Every task must check in regularly,
+/// otherwise the device is reset.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum WatchdogTask {
+    Idle,
+    Blink,
+}
+
+impl WatchdogTask {
+    pub const ALL: [WatchdogTask; 2] = [WatchdogTask::Idle, WatchdogTask::Blink];
+}
+
+pub trait WatchdogDriver: Driver {
+    /// Starts supervising `tasks`, all of which must check in from then on.
+    /// A watchdog that survived a soft reset keeps its previous tasks.
+    fn start(&self, tasks: &[WatchdogTask]);
+
+    fn check_in(&self, task: WatchdogTask);
+
+    /// Task that failed to check in and caused the last reset, if any.
+    fn last_failure(&self) -> Option<WatchdogTask>;
+
+    /// Must be called from the interrupt handler of the watchdog, which
+    /// runs right before the reset.
+    fn on_interrupt();
+}
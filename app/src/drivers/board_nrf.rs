@@ -0,0 +1,186 @@
+//! Maps logical pins to the physical pins of the board selected by a cargo
+//! feature. Drivers only ever name the logical pins.
+
+use nrf52840_hal::gpio::{p0, p1, Disconnected};
+use nrf52840_hal::pac::Peripherals;
+
+#[cfg(not(any(
+    feature = "board-nrf52840-dk",
+    feature = "board-nrf52840-dongle",
+    feature = "board-co2-sensor"
+)))]
+compile_error!("select a board with one of the `board-*` features");
+
+#[cfg(any(
+    all(feature = "board-nrf52840-dk", feature = "board-nrf52840-dongle"),
+    all(feature = "board-nrf52840-dk", feature = "board-co2-sensor"),
+    all(feature = "board-nrf52840-dongle", feature = "board-co2-sensor")
+))]
+compile_error!("only one `board-*` feature may be selected");
+
+// The feature is reserved so that the firmware can be built for the PCB once
+// its pin map has been taken from the schematic.
+#[cfg(feature = "board-co2-sensor")]
+compile_error!("the pin map of the CO2 sensor PCB is not known yet");
+
+#[cfg(feature = "board-nrf52840-dk")]
+pub use dk::*;
+#[cfg(feature = "board-nrf52840-dongle")]
+pub use dongle::*;
+
+/// Devices on the SPI bus, each with its own chip select line.
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum SpiChipSelect {
+    Sensor,
+    Display,
+}
+
+pub struct Pins {
+    pub led: Led,
+    pub button: Button,
+    pub sensor_enable: SensorEnable,
+    pub sensor_ready: SensorReady,
+    pub i2c_scl: I2cScl,
+    pub i2c_sda: I2cSda,
+    pub uart_txd: UartTxd,
+    pub uart_rxd: UartRxd,
+    pub spi_sck: SpiSck,
+    pub spi_mosi: SpiMosi,
+    pub spi_miso: SpiMiso,
+    pub spi_cs_sensor: SpiCsSensor,
+    pub spi_cs_display: SpiCsDisplay,
+    /// Must be AIN0, sees the cell voltage divided by `BATTERY_DIVIDER`.
+    pub battery: Battery,
+    pub led_red: LedRed,
+    pub led_green: LedGreen,
+    pub led_blue: LedBlue,
+    pub buzzer: Buzzer,
+}
+
+impl Pins {
+    /// Recreates the pins, e.g. to return pins that a driver degraded to
+    /// the resources once it is torn down.
+    ///
+    /// # Safety
+    ///
+    /// Only pins whose previous instances have been dropped may be used.
+    pub unsafe fn steal() -> Self {
+        // SAFETY: Creating the ports does not touch the hardware, the caller
+        // ensures that no pin is used twice.
+        let peripherals = unsafe { Peripherals::steal() };
+        Self::new(
+            p0::Parts::new(peripherals.P0),
+            p1::Parts::new(peripherals.P1),
+        )
+    }
+}
+
+/// nRF52840-DK (PCA10056), the buses on the Arduino headers and the battery
+/// on P0.02 (AIN0).
+#[cfg(feature = "board-nrf52840-dk")]
+mod dk {
+    use super::*;
+
+    pub type Led = p0::P0_13<Disconnected>;
+    pub type Button = p0::P0_11<Disconnected>;
+    pub type SensorEnable = p1::P1_10<Disconnected>;
+    pub type SensorReady = p1::P1_08<Disconnected>;
+    pub type I2cScl = p0::P0_27<Disconnected>;
+    pub type I2cSda = p0::P0_26<Disconnected>;
+    pub type UartTxd = p0::P0_06<Disconnected>;
+    pub type UartRxd = p0::P0_08<Disconnected>;
+    pub type SpiSck = p1::P1_15<Disconnected>;
+    pub type SpiMosi = p1::P1_13<Disconnected>;
+    pub type SpiMiso = p1::P1_14<Disconnected>;
+    pub type SpiCsSensor = p1::P1_12<Disconnected>;
+    pub type SpiCsDisplay = p1::P1_11<Disconnected>;
+    pub type Battery = p0::P0_02<Disconnected>;
+    /// The cell is wired to the header through two equal resistors.
+    pub const BATTERY_DIVIDER: u32 = 2;
+    // LED2 to LED4 stand in for the RGB LED.
+    pub type LedRed = p0::P0_14<Disconnected>;
+    pub type LedGreen = p0::P0_15<Disconnected>;
+    pub type LedBlue = p0::P0_16<Disconnected>;
+    pub type Buzzer = p1::P1_04<Disconnected>;
+
+    impl Pins {
+        pub fn new(port0: p0::Parts, port1: p1::Parts) -> Self {
+            Self {
+                led: port0.p0_13,
+                button: port0.p0_11,
+                sensor_enable: port1.p1_10,
+                sensor_ready: port1.p1_08,
+                i2c_scl: port0.p0_27,
+                i2c_sda: port0.p0_26,
+                uart_txd: port0.p0_06,
+                uart_rxd: port0.p0_08,
+                spi_sck: port1.p1_15,
+                spi_mosi: port1.p1_13,
+                spi_miso: port1.p1_14,
+                spi_cs_sensor: port1.p1_12,
+                spi_cs_display: port1.p1_11,
+                battery: port0.p0_02,
+                led_red: port0.p0_14,
+                led_green: port0.p0_15,
+                led_blue: port0.p0_16,
+                buzzer: port1.p1_04,
+            }
+        }
+    }
+}
+
+/// nRF52840 Dongle (PCA10059), peripherals on the castellated pads. The
+/// NFC pads P0.09 and P0.10 are left alone.
+#[cfg(feature = "board-nrf52840-dongle")]
+mod dongle {
+    use super::*;
+
+    // LD1
+    pub type Led = p0::P0_06<Disconnected>;
+    // SW1
+    pub type Button = p1::P1_06<Disconnected>;
+    pub type SensorEnable = p0::P0_15<Disconnected>;
+    pub type SensorReady = p0::P0_29<Disconnected>;
+    pub type I2cScl = p0::P0_22<Disconnected>;
+    pub type I2cSda = p0::P0_24<Disconnected>;
+    pub type UartTxd = p0::P0_20<Disconnected>;
+    pub type UartRxd = p0::P0_17<Disconnected>;
+    pub type SpiSck = p1::P1_15<Disconnected>;
+    pub type SpiMosi = p1::P1_13<Disconnected>;
+    pub type SpiMiso = p1::P1_10<Disconnected>;
+    pub type SpiCsSensor = p0::P0_31<Disconnected>;
+    pub type SpiCsDisplay = p1::P1_00<Disconnected>;
+    pub type Battery = p0::P0_02<Disconnected>;
+    /// The cell is wired to the pad through two equal resistors.
+    pub const BATTERY_DIVIDER: u32 = 2;
+    // LD2, the RGB LED.
+    pub type LedRed = p0::P0_08<Disconnected>;
+    pub type LedGreen = p1::P1_09<Disconnected>;
+    pub type LedBlue = p0::P0_12<Disconnected>;
+    pub type Buzzer = p0::P0_13<Disconnected>;
+
+    impl Pins {
+        pub fn new(port0: p0::Parts, port1: p1::Parts) -> Self {
+            Self {
+                led: port0.p0_06,
+                button: port1.p1_06,
+                sensor_enable: port0.p0_15,
+                sensor_ready: port0.p0_29,
+                i2c_scl: port0.p0_22,
+                i2c_sda: port0.p0_24,
+                uart_txd: port0.p0_20,
+                uart_rxd: port0.p0_17,
+                spi_sck: port1.p1_15,
+                spi_mosi: port1.p1_13,
+                spi_miso: port1.p1_10,
+                spi_cs_sensor: port0.p0_31,
+                spi_cs_display: port1.p1_00,
+                battery: port0.p0_02,
+                led_red: port0.p0_08,
+                led_green: port1.p1_09,
+                led_blue: port0.p0_12,
+                buzzer: port0.p0_13,
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use super::api::{self, flash::*};
+use super::power_nrf::NrfPowerDriver;
+use super::resources_nrf::NrfCeilingLock;
+use core::ops::Range;
+use core::ptr;
+use di::flash::{Flash, FlashBackend};
+use di::resources::Owner;
+use di::singleton::{Singleton, Teardown, TryDefault};
+use di::{InitError, WithDependency};
+use di_macros::{driver, Singleton};
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+use embedded_storage_async::nor_flash as asynch;
+use nrf52840_hal::pac::NVMC;
+
+const WORD_SIZE: usize = 4;
+const PAGE_SIZE: usize = 4096;
+
+// Defined in storage.x.
+extern "C" {
+    static __storage_start: u8;
+    static __storage_end: u8;
+}
+
+/// Programs the storage region through the NVMC. The CPU stalls while the
+/// NVMC is busy, a page erase takes up to 85 ms. Words may only be written
+/// twice between erases (nWRITE), so this is no [`MultiwriteBackend`].
+///
+/// [`MultiwriteBackend`]: di::flash::MultiwriteBackend
+struct NrfNvmc {
+    nvmc: NVMC,
+    region: Range<usize>,
+}
+
+fn storage_region() -> Range<usize> {
+    // SAFETY: Only the addresses of the linker symbols are taken.
+    unsafe { ptr::addr_of!(__storage_start) as usize..ptr::addr_of!(__storage_end) as usize }
+}
+
+impl NrfNvmc {
+    fn new(nvmc: NVMC) -> Self {
+        Self {
+            nvmc,
+            region: storage_region(),
+        }
+    }
+
+    fn wait_ready(&self) {
+        while self.nvmc.ready.read().ready().is_busy() {}
+    }
+}
+
+impl FlashBackend for NrfNvmc {
+    const WORD_SIZE: usize = WORD_SIZE;
+    const PAGE_SIZE: usize = PAGE_SIZE;
+
+    fn capacity(&self) -> usize {
+        self.region.len()
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) {
+        let start = (self.region.start + offset) as *const u8;
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            // SAFETY: The offset has been bounds checked against the region.
+            *byte = unsafe { ptr::read_volatile(start.add(i)) };
+        }
+    }
+
+    fn erase_page(&mut self, offset: usize) {
+        self.nvmc.config.write(|w| w.wen().een());
+        // SAFETY: The page lies within the storage region.
+        self.nvmc
+            .erasepage()
+            .write(|w| unsafe { w.bits((self.region.start + offset) as u32) });
+        self.wait_ready();
+        self.nvmc.config.write(|w| w.wen().ren());
+    }
+
+    fn write_word(&mut self, offset: usize, word: &[u8]) {
+        let value = u32::from_le_bytes(word.try_into().unwrap());
+        self.nvmc.config.write(|w| w.wen().wen());
+        // SAFETY: The word is aligned and lies within the storage region.
+        unsafe { ptr::write_volatile((self.region.start + offset) as *mut u32, value) };
+        self.wait_ready();
+        self.nvmc.config.write(|w| w.wen().ren());
+    }
+}
+
+pub struct NrfFlashState {
+    flash: Flash<NrfNvmc>,
+}
+
+impl TryDefault for NrfFlashState {
+    fn try_default() -> Result<Self, InitError> {
+        Self::with_dependency(|nvmc: NVMC| Self {
+            flash: Flash::new(NrfNvmc::new(nvmc)),
+        })
+    }
+}
+
+impl Teardown for NrfFlashState {
+    fn teardown(self) {
+        Self::return_dependency(self.flash.into_backend().nvmc);
+    }
+}
+
+/// Shared with the tasks that persist settings which run at priority 1.
+#[derive(Singleton)]
+#[singleton(content = NrfFlashState, lock = NrfCeilingLock<1>)]
+struct NrfFlashDriverState;
+
+#[driver(state = NrfFlashDriverState, init = Self::check_region, depends_on(NrfPowerDriver))]
+pub struct NrfFlashDriver;
+
+impl NrfFlashDriver {
+    fn check_region(&self) -> Result<(), api::ApiError> {
+        NrfFlashDriverState::with_ref(|state| {
+            let region = &state.flash.backend().region;
+            if region.start % PAGE_SIZE == 0 && region.len() % PAGE_SIZE == 0 {
+                Ok(())
+            } else {
+                Err(api::ApiError::StorageNotAligned)
+            }
+        })
+    }
+}
+
+impl FlashDriver for NrfFlashDriver {
+    type Storage = NrfFlashStorage;
+
+    fn storage(&self) -> NrfFlashStorage {
+        NrfFlashStorage
+    }
+}
+
+/// Handle of the storage region.
+pub struct NrfFlashStorage;
+
+impl ErrorType for NrfFlashStorage {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for NrfFlashStorage {
+    const READ_SIZE: usize = <Flash<NrfNvmc> as ReadNorFlash>::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind> {
+        NrfFlashDriverState::try_with_ref_mut(|state| {
+            ReadNorFlash::read(&mut state.flash, offset, bytes)
+        })
+        .unwrap_or(Err(NorFlashErrorKind::Other))
+    }
+
+    /// Does not touch the flash, so it is also available while an async
+    /// task holds it.
+    fn capacity(&self) -> usize {
+        storage_region().len()
+    }
+}
+
+impl NorFlash for NrfFlashStorage {
+    const WRITE_SIZE: usize = <Flash<NrfNvmc> as NorFlash>::WRITE_SIZE;
+    const ERASE_SIZE: usize = <Flash<NrfNvmc> as NorFlash>::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
+        NrfFlashDriverState::try_with_ref_mut(|state| NorFlash::erase(&mut state.flash, from, to))
+            .unwrap_or(Err(NorFlashErrorKind::Other))
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+        NrfFlashDriverState::try_with_ref_mut(|state| {
+            NorFlash::write(&mut state.flash, offset, bytes)
+        })
+        .unwrap_or(Err(NorFlashErrorKind::Other))
+    }
+}
+
+/// Reads are memory mapped and return right away.
+impl asynch::ReadNorFlash for NrfFlashStorage {
+    const READ_SIZE: usize = <Self as ReadNorFlash>::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind> {
+        let mut state = NrfFlashDriverState::lock()
+            .await
+            .map_err(|_| NorFlashErrorKind::Other)?;
+        asynch::ReadNorFlash::read(&mut state.flash, offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        ReadNorFlash::capacity(self)
+    }
+}
+
+/// The NVMC has no interrupt. [`Flash`] erases and writes one page at a time
+/// and yields in between, the lock is held throughout.
+impl asynch::NorFlash for NrfFlashStorage {
+    const WRITE_SIZE: usize = <Self as NorFlash>::WRITE_SIZE;
+    const ERASE_SIZE: usize = <Self as NorFlash>::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
+        let mut state = NrfFlashDriverState::lock()
+            .await
+            .map_err(|_| NorFlashErrorKind::Other)?;
+        asynch::NorFlash::erase(&mut state.flash, from, to).await
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+        let mut state = NrfFlashDriverState::lock()
+            .await
+            .map_err(|_| NorFlashErrorKind::Other)?;
+        asynch::NorFlash::write(&mut state.flash, offset, bytes).await
+    }
+}
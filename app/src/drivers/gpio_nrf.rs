@@ -1,69 +1,202 @@
-use super::api::{gpio::*, Driver, DriverStateHolder};
-use super::resources_nrf::NrfDriverResources;
+use super::api::{gpio::*, ApiError};
+use super::board_nrf as board;
+use super::power_nrf::NrfPowerDriver;
+use super::resources_nrf::NrfCeilingLock;
 use core::convert::Infallible;
-use di::singleton::Singleton;
-use di::{Initialized, WithDependency};
-use embedded_hal::digital::OutputPin;
-use nrf52840_hal::{
-    gpio::{self, Level, Output, Pin, PushPull},
-    pac::P0,
-};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+use di::resources::{Composite, Owner};
+use di::singleton::{Singleton, Teardown, TryDefault};
+use di::{InitError, TryInitialized, WithDependency};
+use di_macros::{driver, Dependency, Singleton};
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+use embedded_hal_async::digital::Wait;
+use nrf52840_hal::gpio::{Floating, Input, Level, Output, Pin, Port, PullUp, PushPull};
+use nrf52840_hal::pac::{p0, GPIOTE, P0, P1};
+
+const NUM_INPUTS: usize = 2;
+
+/// GPIOTE channel that watches the sensor's ready line.
+const SENSOR_READY_CHANNEL: usize = 0;
+
+#[derive(Dependency)]
+#[dependency(owner = NrfGpioState)]
+struct NrfGpioOutputs {
+    led: board::Led,
+    sensor_enable: board::SensorEnable,
+}
+
+/// The sensor's ready line is watched by a GPIOTE channel. The button is
+/// watched through the PORT event instead, which unlike an IN channel does
+/// not keep the high frequency clock running while the board waits for a
+/// button press.
+#[derive(Dependency)]
+#[dependency(owner = NrfGpioState)]
+struct NrfGpioInputs {
+    gpiote: GPIOTE,
+    // Active low.
+    button: board::Button,
+    sensor_ready: board::SensorReady,
+}
 
 pub struct NrfGpioState {
     led: Pin<Output<PushPull>>,
+    sensor_enable: Pin<Output<PushPull>>,
+    gpiote: GPIOTE,
+    button: Pin<Input<PullUp>>,
+    sensor_ready: Pin<Input<Floating>>,
+    /// Level of the button when the PORT event was last armed.
+    button_high: bool,
+    /// Number of edges seen on each input, wraps around.
+    edges: [u32; NUM_INPUTS],
+    wakers: [Option<Waker>; NUM_INPUTS],
+    /// Inputs that have been handed out.
+    taken: [bool; NUM_INPUTS],
 }
 
-impl WithDependency<P0> for NrfGpioState {
-    fn with_dependency<Result, F: FnOnce(P0) -> Result>(f: F) -> Result {
-        NrfDriverResources::with_ref_mut(|resources| f(resources.p0.take().unwrap()))
+impl TryDefault for NrfGpioState {
+    fn try_default() -> Result<Self, InitError> {
+        Self::with_dependency(|NrfGpioOutputs { led, sensor_enable }| {
+            Self::with_dependency(
+                |NrfGpioInputs {
+                     gpiote,
+                     button,
+                     sensor_ready,
+                 }| {
+                    let mut state = Self {
+                        led: led.into_push_pull_output(Level::Low).degrade(),
+                        sensor_enable: sensor_enable.into_push_pull_output(Level::Low).degrade(),
+                        gpiote,
+                        button: button.into_pullup_input().degrade(),
+                        sensor_ready: sensor_ready.into_floating_input().degrade(),
+                        button_high: true,
+                        edges: [0; NUM_INPUTS],
+                        wakers: [const { None }; NUM_INPUTS],
+                        taken: [false; NUM_INPUTS],
+                    };
+                    state.watch_sensor_ready();
+                    state.button_high = state.sense_button();
+                    state.gpiote.events_port.reset();
+                    state.gpiote.intenset.write(|w| w.port().set());
+                    state
+                },
+            )
+        })?
     }
 }
 
-impl Default for NrfGpioState {
-    fn default() -> Self {
-        Self::with_dependency(|p0| {
-            let port0 = gpio::p0::Parts::new(p0);
-            Self {
-                led: port0.p0_13.into_push_pull_output(Level::Low).degrade(),
+impl Teardown for NrfGpioState {
+    fn teardown(self) {
+        self.gpiote.config[SENSOR_READY_CHANNEL].reset();
+        self.gpiote.intenclr.write(|w| unsafe { w.bits(u32::MAX) });
+        let Self {
+            led,
+            sensor_enable,
+            gpiote,
+            button,
+            sensor_ready,
+            ..
+        } = self;
+        led.into_disconnected();
+        sensor_enable.into_disconnected();
+        // Also clears SENSE.
+        button.into_disconnected();
+        sensor_ready.into_disconnected();
+        // SAFETY: The pins taken from the board pins were dropped above.
+        let pins = unsafe { board::Pins::steal() };
+        Self::return_dependency(
+            NrfGpioOutputs {
+                led: pins.led,
+                sensor_enable: pins.sensor_enable,
             }
-        })
+            .into_parts(),
+        );
+        Self::return_dependency(
+            NrfGpioInputs {
+                gpiote,
+                button: pins.button,
+                sensor_ready: pins.sensor_ready,
+            }
+            .into_parts(),
+        );
     }
 }
 
-struct NrfGpioDriverState;
-
-impl Singleton for NrfGpioDriverState {
-    type Content = NrfGpioState;
+impl NrfGpioState {
+    /// Raises an interrupt on both edges of the ready line.
+    fn watch_sensor_ready(&self) {
+        let pin = &self.sensor_ready;
+        // SAFETY: Pin numbers are valid PSEL values.
+        self.gpiote.config[SENSOR_READY_CHANNEL].write(|w| unsafe {
+            w.mode()
+                .event()
+                .psel()
+                .bits(pin.pin())
+                .port()
+                .bit(pin.port() == Port::Port1)
+                .polarity()
+                .toggle()
+        });
+        self.gpiote.events_in[SENSOR_READY_CHANNEL].reset();
+        self.gpiote
+            .intenset
+            .write(|w| unsafe { w.bits(1 << SENSOR_READY_CHANNEL) });
+    }
 
-    fn with_state_holder<Result, F>(f: F) -> Result
-    where
-        F: FnOnce(&DriverStateHolder<Self::Content>) -> Result,
-    {
-        static DRIVER_STATE: DriverStateHolder<NrfGpioState> = DriverStateHolder::new();
-        f(&DRIVER_STATE)
+    /// Arms DETECT for the level opposite to the button's current one and
+    /// returns the current one. DETECT rises right away if the button
+    /// changed in between, so no edge is lost.
+    fn sense_button(&mut self) -> bool {
+        let high = self.button.is_high().unwrap();
+        let port: *const p0::RegisterBlock = match self.button.port() {
+            Port::Port0 => P0::ptr(),
+            Port::Port1 => P1::ptr(),
+        };
+        // SAFETY: Only the SENSE field of the button's own pin is changed.
+        let port = unsafe { &*port };
+        port.pin_cnf[self.button.pin() as usize].modify(|_, w| {
+            if high {
+                w.sense().low()
+            } else {
+                w.sense().high()
+            }
+        });
+        high
     }
-}
 
-struct NrfGpioDriver;
+    fn edge(&mut self, name: GpioInputPin) {
+        let index = name as usize;
+        self.edges[index] = self.edges[index].wrapping_add(1);
+        if let Some(waker) = self.wakers[index].take() {
+            waker.wake();
+        }
+    }
 
-impl NrfGpioDriver {
-    const fn new() -> NrfGpioDriver {
-        NrfGpioDriver
+    fn is_high(&mut self, name: GpioInputPin) -> bool {
+        match name {
+            GpioInputPin::Button => self.button.is_high().unwrap(),
+            GpioInputPin::SensorReady => self.sensor_ready.is_high().unwrap(),
+        }
     }
 }
 
-impl Initialized for NrfGpioDriver {
-    fn init(&self) {
-        NrfGpioDriverState.init();
-    }
+/// Shared with the GPIOTE interrupt handler which runs at priority 2.
+#[derive(Singleton)]
+#[singleton(content = NrfGpioState, lock = NrfCeilingLock<2>)]
+struct NrfGpioDriverState;
+
+#[driver(state = NrfGpioDriverState, depends_on(NrfPowerDriver))]
+pub struct NrfGpioDriver;
 
-    fn is_initialized(&self) -> bool {
-        NrfGpioDriverState.is_initialized()
+impl NrfGpioDriver {
+    pub const fn new() -> NrfGpioDriver {
+        NrfGpioDriver
     }
 }
 
 impl GpioDriver for NrfGpioDriver {
     type GpioError = Infallible;
+    type InputPin = NrfInputPin;
 
     fn with_output_pin<F>(name: GpioOutputPin, f: F)
     where
@@ -72,6 +205,7 @@ impl GpioDriver for NrfGpioDriver {
         NrfGpioDriverState::with_ref(|state| {
             let pin = match name {
                 GpioOutputPin::LED => &state.led,
+                GpioOutputPin::SensorEnable => &state.sensor_enable,
             };
             f(pin);
         });
@@ -84,10 +218,145 @@ impl GpioDriver for NrfGpioDriver {
         NrfGpioDriverState::with_ref_mut(|state| {
             let pin = match name {
                 GpioOutputPin::LED => &mut state.led,
+                GpioOutputPin::SensorEnable => &mut state.sensor_enable,
             };
             f(pin);
         });
     }
+
+    fn input_pin(&self, name: GpioInputPin) -> Result<NrfInputPin, ApiError> {
+        NrfGpioDriverState::with_ref_mut(|state| {
+            if core::mem::replace(&mut state.taken[name as usize], true) {
+                Err(ApiError::DeviceInUse)
+            } else {
+                Ok(NrfInputPin { name })
+            }
+        })
+    }
+
+    fn on_interrupt() {
+        NrfGpioDriverState::with_ref_mut(|state| {
+            let gpiote = &state.gpiote;
+            if gpiote.events_in[SENSOR_READY_CHANNEL].read().bits() != 0 {
+                gpiote.events_in[SENSOR_READY_CHANNEL].reset();
+                state.edge(GpioInputPin::SensorReady);
+            }
+            let gpiote = &state.gpiote;
+            if gpiote.events_port.read().bits() != 0 {
+                gpiote.events_port.reset();
+                let high = state.sense_button();
+                if high != state.button_high {
+                    state.button_high = high;
+                    state.edge(GpioInputPin::Button);
+                }
+            }
+        })
+    }
+}
+
+/// The only handle of an input pin, the pin can be taken again once the
+/// handle is dropped.
+pub struct NrfInputPin {
+    name: GpioInputPin,
+}
+
+impl Drop for NrfInputPin {
+    fn drop(&mut self) {
+        // A re-initialized driver starts out with all pins available.
+        if NrfGpioDriverState.is_initialized() {
+            NrfGpioDriverState::with_ref_mut(|state| state.taken[self.name as usize] = false)
+        }
+    }
+}
+
+impl NrfInputPin {
+    fn index(&self) -> usize {
+        self.name as usize
+    }
+
+    /// Waits for the pin to reach the given level. The level is read under
+    /// the interrupt lock, so an edge right after the check still wakes us.
+    async fn wait_for_level(&mut self, high: bool) {
+        let index = self.index();
+        poll_fn(|cx| {
+            NrfGpioDriverState::with_ref_mut(|state| {
+                if state.is_high(self.name) == high {
+                    Poll::Ready(())
+                } else {
+                    state.wakers[index] = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    async fn wait_for_edge(&mut self) {
+        let index = self.index();
+        let edges = NrfGpioDriverState::with_ref(|state| state.edges[index]);
+        poll_fn(|cx| {
+            NrfGpioDriverState::with_ref_mut(|state| {
+                if state.edges[index] != edges {
+                    Poll::Ready(())
+                } else {
+                    state.wakers[index] = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+}
+
+impl ErrorType for NrfInputPin {
+    type Error = Infallible;
 }
 
-impl Driver for NrfGpioDriver {}
+impl InputPin for NrfInputPin {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(NrfGpioDriverState::with_ref_mut(|state| {
+            state.is_high(self.name)
+        }))
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+impl Wait for NrfInputPin {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        self.wait_for_level(true).await;
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        self.wait_for_level(false).await;
+        Ok(())
+    }
+
+    /// Edges are reported on toggles, the level after the edge tells
+    /// their direction.
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        loop {
+            self.wait_for_edge().await;
+            if self.is_high()? {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        loop {
+            self.wait_for_edge().await;
+            if self.is_low()? {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for_edge().await;
+        Ok(())
+    }
+}
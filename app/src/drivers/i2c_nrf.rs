@@ -0,0 +1,473 @@
+use super::api::{self, i2c::*};
+use super::board_nrf as board;
+use super::power_nrf::NrfPowerDriver;
+use super::resources_nrf::{in_data_ram, NrfCeilingLock};
+use core::future::poll_fn;
+use core::sync::atomic::{compiler_fence, AtomicU32, Ordering};
+use core::task::{Poll, Waker};
+use di::resources::{Composite, Owner};
+use di::singleton::{Singleton, Teardown, TryDefault};
+use di::{InitError, TryInitialized, WithDependency};
+use di_macros::{driver, Dependency, Singleton};
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::i2c::{ErrorType, I2c, Operation};
+use nrf52840_hal::gpio::{Level, OpenDrainConfig};
+use nrf52840_hal::pac::{twim0, TWIM0};
+use nrf52840_hal::twim::{self, Frequency, Twim};
+
+const DEFAULT_FREQUENCY: I2cFrequency = I2cFrequency::K100;
+
+/// Half an SCL period at 100 kHz in CPU cycles.
+const RECOVERY_HALF_PERIOD: u32 = 320;
+
+/// ERRORSRC flags, write 1 to clear.
+const ERRORSRC_OVERRUN: u32 = 1 << 0;
+const ERRORSRC_ANACK: u32 = 1 << 1;
+const ERRORSRC_DNACK: u32 = 1 << 2;
+
+/// Addresses that have a device handle, one bit per 7-bit address.
+static DEVICES: [AtomicU32; 4] = [const { AtomicU32::new(0) }; 4];
+
+#[derive(Dependency)]
+#[dependency(owner = NrfI2cState)]
+struct NrfI2cResources {
+    twim0: TWIM0,
+    scl: board::I2cScl,
+    sda: board::I2cSda,
+}
+
+pub struct NrfI2cState {
+    // Only `None` while the bus is being reconfigured.
+    twim: Option<Twim<TWIM0>>,
+    frequency: I2cFrequency,
+}
+
+impl TryDefault for NrfI2cState {
+    fn try_default() -> Result<Self, InitError> {
+        Self::with_dependency(|NrfI2cResources { twim0, scl, sda }: NrfI2cResources| {
+            let pins = twim::Pins {
+                scl: scl.into_floating_input().degrade(),
+                sda: sda.into_floating_input().degrade(),
+            };
+            Self {
+                twim: Some(Twim::new(twim0, pins, frequency(DEFAULT_FREQUENCY))),
+                frequency: DEFAULT_FREQUENCY,
+            }
+        })
+    }
+}
+
+impl Teardown for NrfI2cState {
+    fn teardown(self) {
+        let _ = NrfI2cWaker::deinit();
+        let (twim0, twim::Pins { scl, sda }) = self.twim.unwrap().free();
+        twim0.enable.write(|w| w.enable().disabled());
+        scl.into_disconnected();
+        sda.into_disconnected();
+        // SAFETY: The pins taken from the board pins were dropped above.
+        let pins = unsafe { board::Pins::steal() };
+        Self::return_dependency(
+            NrfI2cResources {
+                twim0,
+                scl: pins.i2c_scl,
+                sda: pins.i2c_sda,
+            }
+            .into_parts(),
+        );
+    }
+}
+
+impl NrfI2cState {
+    /// Releases the pins from the TWIM for `f` and re-creates the TWIM
+    /// afterwards with the current frequency.
+    fn with_pins<Result>(&mut self, f: impl FnOnce(twim::Pins) -> (twim::Pins, Result)) -> Result {
+        let (twim0, pins) = self.twim.take().unwrap().free();
+        let (pins, result) = f(pins);
+        self.twim = Some(Twim::new(twim0, pins, frequency(self.frequency)));
+        result
+    }
+
+    fn twim(&mut self) -> &mut Twim<TWIM0> {
+        self.twim.as_mut().unwrap()
+    }
+}
+
+fn frequency(frequency: I2cFrequency) -> Frequency {
+    match frequency {
+        I2cFrequency::K100 => Frequency::K100,
+        I2cFrequency::K250 => Frequency::K250,
+        I2cFrequency::K400 => Frequency::K400,
+    }
+}
+
+/// Toggles SCL until the device holding SDA low has clocked out the rest of
+/// its byte, then generates a STOP condition. SDA is pulled up while it is
+/// sampled so that a bus without pull-ups does not read as stuck or free at
+/// random.
+fn recover_bus(pins: twim::Pins) -> (twim::Pins, Result<(), api::ApiError>) {
+    let half_period = || cortex_m::asm::delay(RECOVERY_HALF_PERIOD);
+    let twim::Pins { scl, sda } = pins;
+    let mut sda = sda.into_pullup_input();
+
+    let mut scl = scl.into_open_drain_output(OpenDrainConfig::Standard0Disconnect1, Level::High);
+    for _ in 0..9 {
+        if sda.is_high().unwrap() {
+            break;
+        }
+        scl.set_low().unwrap();
+        half_period();
+        scl.set_high().unwrap();
+        half_period();
+    }
+    let result = if sda.is_high().unwrap() {
+        Ok(())
+    } else {
+        Err(api::ApiError::I2cBusStuck)
+    };
+
+    // STOP: SDA rises while SCL is high.
+    let mut sda_out = sda.into_open_drain_output(OpenDrainConfig::Standard0Disconnect1, Level::Low);
+    half_period();
+    sda_out.set_high().unwrap();
+    half_period();
+
+    let pins = twim::Pins {
+        scl: scl.into_floating_input(),
+        sda: sda_out.into_floating_input(),
+    };
+    (pins, result)
+}
+
+/// Waker of the task waiting for a transfer, shared with the TWIM interrupt
+/// handler which runs at priority 2.
+#[derive(Singleton)]
+#[singleton(content = Option<Waker>, lock = NrfCeilingLock<2>)]
+struct NrfI2cWaker;
+
+/// Owned by the task running a transaction, the lock is held across the
+/// transfers of async transactions.
+#[derive(Singleton)]
+#[singleton(content = NrfI2cState, lock = NrfCeilingLock<1>)]
+struct NrfI2cDriverState;
+
+#[driver(state = NrfI2cDriverState, init = Self::start, depends_on(NrfPowerDriver))]
+pub struct NrfI2cDriver;
+
+impl NrfI2cDriver {
+    fn start(&self) -> Result<(), InitError> {
+        NrfI2cWaker.try_init()?;
+        Ok(self.recover()?)
+    }
+}
+
+impl I2cDriver for NrfI2cDriver {
+    type Device = NrfI2cDevice;
+
+    fn device(&self, address: u8) -> Result<NrfI2cDevice, api::ApiError> {
+        let (word, bit) = (usize::from(address >> 5) & 3, 1 << (address & 31));
+        if DEVICES[word].fetch_or(bit, Ordering::Relaxed) & bit != 0 {
+            return Err(api::ApiError::DeviceInUse);
+        }
+        Ok(NrfI2cDevice { address })
+    }
+
+    fn set_frequency(&self, frequency: I2cFrequency) -> Result<(), api::ApiError> {
+        NrfI2cDriverState::try_with_ref_mut(|state| {
+            state.frequency = frequency;
+            state.with_pins(|pins| (pins, ()));
+        })?;
+        Ok(())
+    }
+
+    fn recover(&self) -> Result<(), api::ApiError> {
+        NrfI2cDriverState::try_with_ref_mut(|state| state.with_pins(recover_bus))?
+    }
+
+    fn on_interrupt() {
+        // SAFETY: Only the interrupts are disabled, the waiting task handles
+        // the events.
+        let twim = unsafe { &*TWIM0::ptr() };
+        twim.intenclr
+            .write(|w| w.stopped().clear().suspended().clear().error().clear());
+        NrfI2cWaker::with_ref_mut(|waker| {
+            if let Some(waker) = waker.take() {
+                waker.wake();
+            }
+        })
+    }
+}
+
+/// Handle of the device at `address`. There is at most one handle per
+/// address, it only talks to its own device. Transactions of different
+/// handles are served in the order they were issued.
+pub struct NrfI2cDevice {
+    address: u8,
+}
+
+impl NrfI2cDevice {
+    fn check_address(&self, address: u8) -> Result<(), api::BusError<twim::Error>> {
+        if address == self.address {
+            Ok(())
+        } else {
+            Err(api::BusError::WrongAddress)
+        }
+    }
+}
+
+impl Drop for NrfI2cDevice {
+    fn drop(&mut self) {
+        let (word, bit) = (usize::from(self.address >> 5) & 3, 1 << (self.address & 31));
+        DEVICES[word].fetch_and(!bit, Ordering::Relaxed);
+    }
+}
+
+impl ErrorType for NrfI2cDevice {
+    type Error = api::BusError<twim::Error>;
+}
+
+impl I2c for NrfI2cDevice {
+    /// Blocks until the transfers are done. Fails with `Busy` while an async
+    /// transaction holds the bus.
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.check_address(address)?;
+        NrfI2cDriverState::try_with_ref_mut(|state| state.twim().transaction(address, operations))?
+            .map_err(api::BusError::Bus)
+    }
+}
+
+impl embedded_hal_async::i2c::I2c for NrfI2cDevice {
+    /// Waits for the bus and for the end of every transfer without blocking.
+    /// Buffers must be in RAM, EasyDMA accesses them directly. Adjacent
+    /// reads fail with `Unsupported`.
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.check_address(address)?;
+        let _state = NrfI2cDriverState::lock().await?;
+        // SAFETY: The locked state owns the TWIM, the HAL is bypassed for
+        // the async transfers only.
+        let twim = unsafe { &*TWIM0::ptr() };
+        let mut transfer = Transfer {
+            twim,
+            running: false,
+        };
+        let result = transfer.run(address, operations).await;
+        transfer.finish();
+        result
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Write,
+    Read,
+}
+
+/// Polls of the STOPPED event, well over the few SCL periods a STOP takes at
+/// 100 kHz. A device holding SCL low keeps the TWIM from stopping.
+const STOP_POLLS: u32 = 10_000;
+
+/// Transfers of an async transaction. Stops the bus if the transaction is
+/// cancelled, so EasyDMA does not touch the buffers after they are gone.
+struct Transfer<'a> {
+    twim: &'a twim0::RegisterBlock,
+    running: bool,
+}
+
+impl Transfer<'_> {
+    /// Runs the operations back to back. Operations in the same direction
+    /// continue the transfer, a change of direction repeats the START.
+    ///
+    /// Writes suspend the TWIM while the next operation is set up. Reads end
+    /// with a STOP or, through a short, start the next write right away, so
+    /// its buffer is set up before the read. Adjacent reads would need the
+    /// TWIM to suspend after a read and are not supported.
+    async fn run(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), api::BusError<twim::Error>> {
+        let twim = self.twim;
+        // SAFETY: Any 7-bit address is valid.
+        twim.address.write(|w| unsafe { w.address().bits(address) });
+        let mut suspended = false;
+        let mut operations = operations.iter_mut().peekable();
+        while let Some(operation) = operations.next() {
+            let start = match operation {
+                Operation::Write(bytes) => {
+                    self.set_tx(bytes)?;
+                    let last = operations.peek().is_none();
+                    twim.shorts.write(|w| {
+                        if last {
+                            w.lasttx_stop().enabled()
+                        } else {
+                            w.lasttx_suspend().enabled()
+                        }
+                    });
+                    // A write continues a suspended write.
+                    (!suspended).then_some(Direction::Write)
+                }
+                Operation::Read(bytes) => {
+                    self.set_rx(bytes)?;
+                    match operations.next() {
+                        None => twim.shorts.write(|w| w.lastrx_stop().enabled()),
+                        Some(Operation::Write(bytes)) => {
+                            self.set_tx(bytes)?;
+                            let last = operations.peek().is_none();
+                            twim.shorts.write(|w| {
+                                let w = w.lastrx_starttx().enabled();
+                                if last {
+                                    w.lasttx_stop().enabled()
+                                } else {
+                                    w.lasttx_suspend().enabled()
+                                }
+                            });
+                        }
+                        Some(Operation::Read(_)) => return Err(api::BusError::Unsupported),
+                    }
+                    Some(Direction::Read)
+                }
+            };
+            let last = operations.peek().is_none();
+            twim.events_stopped.reset();
+            twim.events_suspended.reset();
+            twim.events_error.reset();
+            // The buffers must be written before EasyDMA reads them.
+            compiler_fence(Ordering::SeqCst);
+            // SAFETY: Triggering a task has no preconditions.
+            match start {
+                Some(Direction::Write) => twim.tasks_starttx.write(|w| unsafe { w.bits(1) }),
+                Some(Direction::Read) => twim.tasks_startrx.write(|w| unsafe { w.bits(1) }),
+                None => {}
+            }
+            if suspended {
+                twim.tasks_resume.write(|w| unsafe { w.bits(1) });
+            }
+            self.running = true;
+            self.wait(last).await.map_err(api::BusError::Bus)?;
+            suspended = !last;
+        }
+        Ok(())
+    }
+
+    fn set_tx(&self, bytes: &[u8]) -> Result<(), api::BusError<twim::Error>> {
+        if !in_data_ram(bytes) {
+            return Err(api::BusError::Bus(twim::Error::DMABufferNotInDataMemory));
+        }
+        let len = u16::try_from(bytes.len())
+            .map_err(|_| api::BusError::Bus(twim::Error::TxBufferTooLong))?;
+        // SAFETY: The buffer is in RAM and outlives the transfer.
+        self.twim
+            .txd
+            .ptr
+            .write(|w| unsafe { w.ptr().bits(bytes.as_ptr() as u32) });
+        self.twim
+            .txd
+            .maxcnt
+            .write(|w| unsafe { w.maxcnt().bits(len) });
+        Ok(())
+    }
+
+    fn set_rx(&self, bytes: &mut [u8]) -> Result<(), api::BusError<twim::Error>> {
+        let len = u16::try_from(bytes.len())
+            .map_err(|_| api::BusError::Bus(twim::Error::RxBufferTooLong))?;
+        // SAFETY: See `set_tx()`.
+        self.twim
+            .rxd
+            .ptr
+            .write(|w| unsafe { w.ptr().bits(bytes.as_mut_ptr() as u32) });
+        self.twim
+            .rxd
+            .maxcnt
+            .write(|w| unsafe { w.maxcnt().bits(len) });
+        Ok(())
+    }
+
+    /// Waits for the operation to end, i.e. for STOPPED after the last one
+    /// and for SUSPENDED after all others.
+    async fn wait(&mut self, last: bool) -> Result<(), twim::Error> {
+        let twim = self.twim;
+        poll_fn(|cx| {
+            NrfI2cWaker::with_ref_mut(|waker| *waker = Some(cx.waker().clone()));
+            if twim.events_error.read().bits() != 0 {
+                return Poll::Ready(Err(self.fail()));
+            }
+            let ended = if last {
+                twim.events_stopped.read().bits() != 0
+            } else {
+                twim.events_suspended.read().bits() != 0
+            };
+            if ended {
+                if last {
+                    self.running = false;
+                }
+                // EasyDMA has written the buffers before the event.
+                compiler_fence(Ordering::SeqCst);
+                return Poll::Ready(Ok(()));
+            }
+            twim.intenset
+                .write(|w| w.stopped().set().suspended().set().error().set());
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Stops the bus after an error and reports the cause: a NACK of the
+    /// address or of a data byte, or an overrun of the RX buffer.
+    fn fail(&mut self) -> twim::Error {
+        let errorsrc = self.twim.errorsrc.read().bits();
+        // SAFETY: The flags are cleared by writing them back.
+        self.twim.errorsrc.write(|w| unsafe { w.bits(errorsrc) });
+        self.stop();
+        if errorsrc & ERRORSRC_ANACK != 0 {
+            twim::Error::AddressNack
+        } else if errorsrc & ERRORSRC_DNACK != 0 {
+            twim::Error::DataNack
+        } else if errorsrc & ERRORSRC_OVERRUN != 0 {
+            twim::Error::Overrun
+        } else {
+            twim::Error::Transmit
+        }
+    }
+
+    /// Generates a STOP and waits for it. If a device holds SCL low the
+    /// TWIM cannot send the STOP, it is then reset by disabling and
+    /// enabling it, which ends the transfer. The device may still hold the
+    /// bus until `I2cDriver::recover()` clocks it free.
+    fn stop(&mut self) {
+        let twim = self.twim;
+        // SAFETY: Triggering a task has no preconditions.
+        twim.tasks_stop.write(|w| unsafe { w.bits(1) });
+        twim.tasks_resume.write(|w| unsafe { w.bits(1) });
+        let stopped = (0..STOP_POLLS).any(|_| twim.events_stopped.read().bits() != 0);
+        if !stopped {
+            defmt::warn!("I2C: the bus did not stop, resetting the TWIM.");
+            twim.enable.write(|w| w.enable().disabled());
+            twim.enable.write(|w| w.enable().enabled());
+        }
+        self.running = false;
+    }
+
+    /// Leaves the TWIM as the HAL expects it.
+    fn finish(&mut self) {
+        if self.running {
+            self.stop();
+        }
+        self.twim
+            .intenclr
+            .write(|w| w.stopped().clear().suspended().clear().error().clear());
+        self.twim.shorts.reset();
+    }
+}
+
+impl Drop for Transfer<'_> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
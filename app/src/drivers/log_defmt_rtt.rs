@@ -1,20 +1,12 @@
-use super::api::{log::LogDriver, Driver, StatelessDriver};
-use di::Initialized;
-
+use super::api::log::LogDriver;
+use super::soc_cortex_m::SocCortexMDriver;
+use defmt_rtt as _;
+use di_macros::driver;
+
+// Comes after the cycle counter so that the init of every other driver is
+// timed.
+#[driver(after(SocCortexMDriver))]
 #[derive(Default)]
-struct DefmtRttDriver(StatelessDriver);
-
-impl Initialized for DefmtRttDriver {
-    fn init(&self) {
-        self.0.init();
-        use defmt_rtt as _;
-    }
-
-    fn is_initialized(&self) -> bool {
-        self.0.is_initialized()
-    }
-}
-
-impl Driver for DefmtRttDriver {}
+pub struct DefmtRttDriver;
 
 impl LogDriver for DefmtRttDriver {}
@@ -1,10 +1,12 @@
-use super::api::StatelessDriver;
-use super::api::{self, mono::*, osc::OscillatorDriver, Driver};
-use super::osc_nrf::NrfSleepOscillatorDriver;
-use super::resources_nrf::NrfDriverResources;
-use di::singleton::Singleton;
-use di::{Initialized, WithDependency};
-use nrf52840_hal::pac::RTC0;
+use super::api::{self, mono::*, osc::OscillatorDriver};
+use super::osc_nrf::{NrfSleepOscToken, NrfSleepOscillatorDriver};
+use cortex_m::peripheral::NVIC;
+use di::resources::Owner;
+use di::singleton::{Teardown, TryDefault};
+use di::token::SharedToken;
+use di::InitError;
+use di_macros::{driver, Singleton};
+use nrf52840_hal::pac::{Interrupt, Peripherals, RTC0};
 use rtic_monotonics::{Monotonic, TimerQueueBasedMonotonic};
 
 mod private {
@@ -12,32 +14,43 @@ mod private {
     nrf_rtc0_monotonic!(Rtc0Mono);
 }
 
-pub struct NrfRticMonoDriver(StatelessDriver);
-
-impl WithDependency<RTC0> for NrfRticMonoDriver {
-    fn with_dependency<Result, F: FnOnce(RTC0) -> Result>(f: F) -> Result
-    where
-        Self: Sized,
-    {
-        NrfDriverResources::with_ref_mut(|resources| f(resources.rtc0.take().unwrap()))
-    }
+pub struct NrfMonoState {
+    /// Keeps the sleep oscillator running for as long as the RTC counts.
+    sleep_osc: SharedToken<'static, NrfSleepOscToken>,
 }
 
-impl<'a> Initialized for NrfRticMonoDriver {
-    fn init(&self) {
-        self.0.ensure_is_initialized();
-        NrfSleepOscillatorDriver.ensure_is_initialized();
-        NrfSleepOscillatorDriver::request();
-        Self::with_dependency(|rtc0| {
-            private::Rtc0Mono::start(rtc0);
-        });
+impl TryDefault for NrfMonoState {
+    fn try_default() -> Result<Self, InitError> {
+        let sleep_osc = NrfSleepOscillatorDriver::request()?;
+        Self::try_with_dependency(|rtc0: RTC0| private::Rtc0Mono::start(rtc0))?;
+        Ok(Self { sleep_osc })
     }
+}
 
-    fn is_initialized(&self) -> bool {
-        self.0.is_initialized()
+impl Teardown for NrfMonoState {
+    /// Pending delays never expire once the RTC is stopped, so the driver
+    /// must only be deinitialized when no task waits on it.
+    fn teardown(self) {
+        // SAFETY: The monotonic took the only instance of the peripheral
+        // and no longer touches it once its interrupt is masked.
+        let rtc0 = unsafe { Peripherals::steal() }.RTC0;
+        NVIC::mask(Interrupt::RTC0);
+        rtc0.tasks_stop.write(|w| unsafe { w.bits(1) });
+        rtc0.intenclr.write(|w| unsafe { w.bits(u32::MAX) });
+        rtc0.evtenclr.write(|w| unsafe { w.bits(u32::MAX) });
+        Self::return_dependency(rtc0);
+        // Stops the sleep oscillator unless another driver still holds it.
+        drop(self.sleep_osc);
     }
 }
 
+#[derive(Singleton)]
+#[singleton(content = NrfMonoState)]
+struct NrfMonoDriverState;
+
+#[driver(state = NrfMonoDriverState, depends_on(NrfSleepOscillatorDriver))]
+pub struct NrfRticMonoDriver;
+
 pub type Instant = <private::Rtc0Mono as TimerQueueBasedMonotonic>::Instant;
 pub type Duration = <private::Rtc0Mono as TimerQueueBasedMonotonic>::Duration;
 
@@ -64,7 +77,7 @@ impl MonoDriver for NrfRticMonoDriver {
     ) -> Result<F::Output, api::ApiError> {
         private::Rtc0Mono::timeout_at::<F>(instant, future)
             .await
-            .map_err(|_| api::TIMEOUT)
+            .map_err(|_| api::ApiError::Timeout)
     }
 
     async fn timeout_after<F: core::future::Future>(
@@ -74,8 +87,6 @@ impl MonoDriver for NrfRticMonoDriver {
     ) -> Result<F::Output, api::ApiError> {
         private::Rtc0Mono::timeout_after::<F>(duration, future)
             .await
-            .map_err(|_| api::TIMEOUT)
+            .map_err(|_| api::ApiError::Timeout)
     }
 }
-
-impl Driver for NrfRticMonoDriver {}
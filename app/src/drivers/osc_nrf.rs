@@ -1,15 +1,17 @@
-use super::api::{self, osc::*, Driver, DriverStateHolder};
-use super::resources_nrf::NrfDriverResources;
+use super::api::{self, osc::*};
+use super::power_nrf::NrfPowerDriver;
 use core::mem::MaybeUninit;
 use core::result::Result;
-use di::singleton::Singleton;
+use di::resources::Owner;
+use di::singleton::{Singleton, Teardown, TryDefault};
 use di::token::{Release, SharedToken};
-use di::{Initialized, WithDependency};
+use di::{InitError, WithDependency};
+use di_macros::{driver, Singleton};
 use nrf52840_hal::clocks::{
     Clocks as NrfClocks, ExternalOscillator, Internal, LfOscConfiguration, LfOscStarted,
     LfOscStopped, HFCLK_FREQ, LFCLK_FREQ,
 };
-use nrf52840_hal::pac::CLOCK;
+use nrf52840_hal::pac::{Peripherals, CLOCK};
 
 // TODO: Make this configurable.
 const HFXO_ACCURACY: Ppm = Ppm(30);
@@ -17,29 +19,42 @@ const LFXO_ACCURACY: Ppm = Ppm(50);
 const LFXO_CONFIGURATION: LfOscConfiguration = LfOscConfiguration::NoExternalNoBypass;
 type LfOscType = ExternalOscillator;
 
+/// Polls of the HFCLKSTARTED event, a few milliseconds at 64 MHz. The
+/// crystal typically starts within 0.36 ms.
+const HFXO_START_POLLS: u32 = 100_000;
+
 pub struct DontCare;
 
-enum NrfOscState {
+pub(super) enum NrfOscState {
     OffOff(NrfClocks<Internal, LfOscType, LfOscStopped>),
     OffOn(NrfClocks<Internal, LfOscType, LfOscStarted>),
     OnOff(NrfClocks<ExternalOscillator, LfOscType, LfOscStopped>),
     OnOn(NrfClocks<ExternalOscillator, LfOscType, LfOscStarted>),
 }
 
-impl WithDependency<CLOCK> for NrfOscState {
-    fn with_dependency<Result, F: FnOnce(CLOCK) -> Result>(f: F) -> Result {
-        NrfDriverResources::with_ref_mut(|resources| f(resources.clock.take().unwrap()))
-    }
-}
-
-impl Default for NrfOscState {
-    fn default() -> Self {
+impl TryDefault for NrfOscState {
+    fn try_default() -> Result<Self, InitError> {
         Self::with_dependency(|clock| {
             Self::OffOff(NrfClocks::new(clock).set_lfclk_src_external(LFXO_CONFIGURATION))
         })
     }
 }
 
+impl Teardown for NrfOscState {
+    fn teardown(self) {
+        let clocks = match self {
+            Self::OffOff(clocks) => clocks,
+            Self::OffOn(clocks) => clocks.stop_lfclk(),
+            Self::OnOff(clocks) => clocks.disable_ext_hfosc(),
+            Self::OnOn(clocks) => clocks.disable_ext_hfosc().stop_lfclk(),
+        };
+        drop(clocks);
+        // SAFETY: The dropped clocks owned the only instance of the peripheral.
+        let clock = unsafe { Peripherals::steal() }.CLOCK;
+        Self::return_dependency(clock);
+    }
+}
+
 // SAFETY: The LF and HF clock registers can be accessed independently from each other.
 type NrfSleepOscState = NrfClocks<DontCare, LfOscType, LfOscStarted>;
 type NrfHighAccOscState = NrfClocks<ExternalOscillator, DontCare, DontCare>;
@@ -110,20 +125,30 @@ impl Release for NrfHighAccOscToken {
     }
 }
 
-struct NrfOscDriverState;
-
-impl Singleton for NrfOscDriverState {
-    type Content = NrfOscState;
-
-    fn with_state_holder<Result, F>(f: F) -> Result
-    where
-        F: FnOnce(&'static DriverStateHolder<Self::Content>) -> Result,
-    {
-        static DRIVER_STATE: DriverStateHolder<NrfOscState> = DriverStateHolder::new();
-        f(&DRIVER_STATE)
+di::holders!(NrfSleepOscToken, NrfHighAccOscToken);
+
+/// Starts the HFXO and waits for it to run. `enable_ext_hfosc()` waits for
+/// the same event without a timeout, it returns immediately as the event is
+/// left set.
+fn start_hfxo() -> bool {
+    // SAFETY: Only called while the oscillator state, which owns the CLOCK
+    // peripheral, is locked.
+    let clock = unsafe { &*CLOCK::ptr() };
+    // An event left over by the bootloader or an earlier start would end the
+    // wait before the crystal runs.
+    clock.events_hfclkstarted.write(|w| unsafe { w.bits(0) });
+    clock.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+    let started = (0..HFXO_START_POLLS).any(|_| clock.events_hfclkstarted.read().bits() != 0);
+    if !started {
+        clock.tasks_hfclkstop.write(|w| unsafe { w.bits(1) });
     }
+    started
 }
 
+#[derive(Singleton)]
+#[singleton(content = NrfOscState)]
+struct NrfOscDriverState;
+
 impl NrfOscDriverState {
     fn request_sleep_osc() -> Result<SharedToken<'static, NrfSleepOscToken>, api::ApiError> {
         Self::with(|prev_driver_state| {
@@ -150,13 +175,20 @@ impl NrfOscDriverState {
 
     fn request_high_acc_osc() -> Result<SharedToken<'static, NrfHighAccOscToken>, api::ApiError> {
         Self::with(|prev_driver_state| {
+            let is_off = matches!(
+                prev_driver_state,
+                NrfOscState::OffOff(_) | NrfOscState::OffOn(_)
+            );
+            if is_off && !start_hfxo() {
+                return (prev_driver_state, Err(api::ApiError::HfxoNotStarted));
+            }
             let next_driver_state = match prev_driver_state {
                 NrfOscState::OffOff(clocks) => NrfOscState::OnOff(clocks.enable_ext_hfosc()),
                 NrfOscState::OffOn(clocks) => NrfOscState::OnOn(clocks.enable_ext_hfosc()),
                 _ => prev_driver_state,
             };
-            (next_driver_state, ())
-        });
+            (next_driver_state, Ok(()))
+        })?;
         Ok(SharedToken::new(Default::default()))
     }
 
@@ -172,6 +204,12 @@ impl NrfOscDriverState {
     }
 }
 
+/// Owns the CLOCK peripheral on behalf of both oscillator drivers, which
+/// keep it up for as long as either of them is.
+#[driver(state = NrfOscDriverState, depends_on(NrfPowerDriver))]
+pub struct NrfClockDriver;
+
+#[driver(depends_on(NrfClockDriver))]
 pub struct NrfSleepOscillatorDriver;
 
 impl NrfSleepOscillatorDriver {
@@ -180,16 +218,6 @@ impl NrfSleepOscillatorDriver {
     }
 }
 
-impl Initialized for NrfSleepOscillatorDriver {
-    fn init(&self) {
-        NrfOscDriverState.init();
-    }
-
-    fn is_initialized(&self) -> bool {
-        NrfOscDriverState.is_initialized()
-    }
-}
-
 impl<'a> OscillatorDriver<'a> for NrfSleepOscillatorDriver {
     type OscToken = NrfSleepOscToken;
 
@@ -206,16 +234,7 @@ impl<'a> OscillatorDriver<'a> for NrfSleepOscillatorDriver {
     }
 }
 
-impl Driver for NrfSleepOscillatorDriver {}
-
-struct NrfHfOscToken(NrfClocks<ExternalOscillator, DontCare, DontCare>);
-
-impl Drop for NrfHfOscToken {
-    fn drop(&mut self) {
-        todo!()
-    }
-}
-
+#[driver(depends_on(NrfClockDriver))]
 pub struct NrfHighAccOscillatorDriver;
 
 impl<'a> NrfHighAccOscillatorDriver {
@@ -224,16 +243,6 @@ impl<'a> NrfHighAccOscillatorDriver {
     }
 }
 
-impl Initialized for NrfHighAccOscillatorDriver {
-    fn init(&self) {
-        NrfOscDriverState.init();
-    }
-
-    fn is_initialized(&self) -> bool {
-        NrfOscDriverState.is_initialized()
-    }
-}
-
 impl<'a> OscillatorDriver<'a> for NrfHighAccOscillatorDriver {
     type OscToken = NrfHighAccOscToken;
 
@@ -249,5 +258,3 @@ impl<'a> OscillatorDriver<'a> for NrfHighAccOscillatorDriver {
         NrfOscDriverState::request_high_acc_osc()
     }
 }
-
-impl Driver for NrfHighAccOscillatorDriver {}
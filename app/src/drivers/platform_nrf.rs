@@ -0,0 +1,191 @@
+use super::adc_nrf::NrfAdcDriver;
+use super::api::platform::Platform;
+use super::flash_nrf::NrfFlashDriver;
+use super::gpio_nrf::NrfGpioDriver;
+use super::i2c_nrf::NrfI2cDriver;
+use super::log_defmt_rtt::DefmtRttDriver;
+use super::mono_nrf_rtic::NrfRticMonoDriver;
+use super::osc_nrf::{NrfClockDriver, NrfHighAccOscillatorDriver, NrfSleepOscillatorDriver};
+use super::power_nrf::NrfPowerDriver;
+use super::pwm_nrf::NrfPwmDriver;
+use super::resources_nrf::{self, pac, NrfCeilingLock};
+use super::rng_nrf::NrfRngDriver;
+use super::soc_cortex_m::SocCortexMDriver;
+use super::spi_nrf::NrfSpiDriver;
+use super::uart_nrf::NrfUartDriver;
+use super::usb_nrf::{NrfUsbBus, NrfUsbDriver};
+use super::watchdog_nrf::NrfWatchdogDriver;
+use super::Drivers;
+use crate::buffers;
+use defmt::Display2Format;
+use di::driver::{Driver, DriverDescriptor, InitOrder, InitStatus};
+use di::registry::Registry;
+
+/// The nRF52840 platform.
+pub struct NrfPlatform;
+
+impl Platform for NrfPlatform {
+    type Rng = NrfRngDriver;
+    type Mono = NrfRticMonoDriver;
+    type Gpio = NrfGpioDriver;
+    type I2c = NrfI2cDriver;
+    type Spi = NrfSpiDriver;
+    type Uart = NrfUartDriver;
+    type Adc = NrfAdcDriver;
+    type Pwm = NrfPwmDriver;
+    type Watchdog = NrfWatchdogDriver;
+    type Flash = NrfFlashDriver;
+    type UsbBus = NrfUsbBus;
+    type Usb = NrfUsbDriver;
+    type CeilingLock<const CEILING: u8> = NrfCeilingLock<CEILING>;
+    type Peripherals = pac::Peripherals;
+
+    fn init(peripherals: pac::Peripherals) -> Drivers<Self> {
+        init(peripherals)
+    }
+
+    fn dump_status() {
+        dump_status()
+    }
+}
+
+/// All drivers of the platform. Each driver declares its own dependencies,
+/// the order in which drivers appear here is irrelevant.
+const DRIVERS: &[&DriverDescriptor] = &[
+    &SocCortexMDriver::DESCRIPTOR,
+    &DefmtRttDriver::DESCRIPTOR,
+    &NrfPowerDriver::DESCRIPTOR,
+    &NrfClockDriver::DESCRIPTOR,
+    &NrfSleepOscillatorDriver::DESCRIPTOR,
+    &NrfHighAccOscillatorDriver::DESCRIPTOR,
+    &NrfRngDriver::DESCRIPTOR,
+    &NrfRticMonoDriver::DESCRIPTOR,
+    &NrfGpioDriver::DESCRIPTOR,
+    &NrfI2cDriver::DESCRIPTOR,
+    &NrfSpiDriver::DESCRIPTOR,
+    &NrfUartDriver::DESCRIPTOR,
+    &NrfAdcDriver::DESCRIPTOR,
+    &NrfPwmDriver::DESCRIPTOR,
+    &NrfWatchdogDriver::DESCRIPTOR,
+    &NrfFlashDriver::DESCRIPTOR,
+    &NrfUsbDriver::DESCRIPTOR,
+];
+
+const INIT_ORDER: InitOrder<{ DRIVERS.len() }> = match InitOrder::resolve(DRIVERS) {
+    Ok(init_order) => init_order,
+    Err(_) => panic!("cannot resolve the driver dependency graph"),
+};
+
+static REGISTRY: Registry<{ DRIVERS.len() }> = Registry::new(&INIT_ORDER, buffers::POOLS);
+
+pub fn registry() -> &'static Registry<{ DRIVERS.len() }> {
+    &REGISTRY
+}
+
+/// Instantiate drivers that take ownership of the peripherals.
+fn init(peripherals: pac::Peripherals) -> Drivers<NrfPlatform> {
+    // Drivers fail to claim their peripherals without the resources.
+    if let Err(err) = resources_nrf::init(peripherals) {
+        defmt::error!("Cannot take the peripherals: {}", Display2Format(&err));
+    }
+
+    defmt::info!("Initializing {=usize} drivers", INIT_ORDER.len());
+    // Started up front so that the init time of every driver is measured.
+    SocCortexMDriver::start_cycle_counter();
+    let report = REGISTRY.try_init_all(SocCortexMDriver::cycles);
+    dump_status();
+
+    Drivers {
+        rng: report
+            .is_initialized(&NrfRngDriver::DESCRIPTOR)
+            .then_some(NrfRngDriver::new()),
+        mono: report
+            .is_initialized(&NrfRticMonoDriver::DESCRIPTOR)
+            .then_some(NrfRticMonoDriver),
+        gpio: report
+            .is_initialized(&NrfGpioDriver::DESCRIPTOR)
+            .then_some(NrfGpioDriver::new()),
+        i2c: report
+            .is_initialized(&NrfI2cDriver::DESCRIPTOR)
+            .then_some(NrfI2cDriver),
+        spi: report
+            .is_initialized(&NrfSpiDriver::DESCRIPTOR)
+            .then_some(NrfSpiDriver),
+        uart: report
+            .is_initialized(&NrfUartDriver::DESCRIPTOR)
+            .then_some(NrfUartDriver),
+        adc: report
+            .is_initialized(&NrfAdcDriver::DESCRIPTOR)
+            .then_some(NrfAdcDriver),
+        pwm: report
+            .is_initialized(&NrfPwmDriver::DESCRIPTOR)
+            .then_some(NrfPwmDriver),
+        watchdog: report
+            .is_initialized(&NrfWatchdogDriver::DESCRIPTOR)
+            .then_some(NrfWatchdogDriver),
+        flash: report
+            .is_initialized(&NrfFlashDriver::DESCRIPTOR)
+            .then_some(NrfFlashDriver),
+        usb: report
+            .is_initialized(&NrfUsbDriver::DESCRIPTOR)
+            .then_some(&NrfUsbDriver),
+    }
+}
+
+/// Prints the state of all drivers, singletons, shared tokens and buffer
+/// pools.
+fn dump_status() {
+    defmt::info!("Drivers:");
+    for driver in REGISTRY.drivers() {
+        let name = driver.descriptor.name();
+        match driver.status {
+            None => defmt::info!("  {=str}: not initialized", name),
+            Some(InitStatus::Initialized) => defmt::info!(
+                "  {=str}: initialized in {=u32} cycles, up: {=bool}",
+                name,
+                driver.init_ticks.unwrap_or_default(),
+                driver.is_initialized()
+            ),
+            Some(InitStatus::Failed(err)) => {
+                defmt::error!("  {=str}: {}", name, Display2Format(&err))
+            }
+            Some(InitStatus::Skipped { dependency }) => defmt::warn!(
+                "  {=str}: skipped, {=str} not initialized",
+                name,
+                dependency
+            ),
+        }
+        for dependency in driver.descriptor.depends_on() {
+            defmt::debug!("    depends on {=str}", dependency.name());
+        }
+    }
+
+    defmt::info!("Singletons:");
+    for singleton in REGISTRY.singletons() {
+        defmt::info!(
+            "  {=str}: initialized: {=bool}",
+            singleton.name(),
+            singleton.is_initialized()
+        );
+    }
+
+    defmt::info!("Shared tokens:");
+    for token in REGISTRY.tokens() {
+        defmt::info!("  {=str}: {=usize} holders", token.name(), token.count());
+    }
+
+    defmt::info!("Buffer pools:");
+    for pool in REGISTRY.pools() {
+        defmt::info!(
+            "  {=str}: {=usize}/{=usize} available, {=usize} bytes",
+            pool.name(),
+            pool.available(),
+            pool.capacity(),
+            pool.reserved()
+        );
+    }
+    defmt::info!(
+        "Reserved {=usize} bytes of buffer memory",
+        REGISTRY.reserved()
+    );
+}
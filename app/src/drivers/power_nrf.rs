@@ -1,35 +1,67 @@
 use super::api::power::PowerDriver;
-use super::api::{Driver, StatelessDriver};
-use super::resources_nrf::NrfDriverResources;
-use di::singleton::Singleton;
-use di::{Initialized, WithDependency};
+use super::log_defmt_rtt::DefmtRttDriver;
+use di::resources::Owner;
+use di::singleton::{Singleton, Teardown, TryDefault};
+use di::{InitError, WithDependency};
+use di_macros::{driver, Singleton};
 use nrf52840_hal::pac::POWER;
 
-struct NrfPowerDriver(StatelessDriver);
-
-impl WithDependency<POWER> for NrfPowerDriver {
-    fn with_dependency<Result, F: FnOnce(POWER) -> Result>(f: F) -> Result
-    where
-        Self: Sized,
-    {
-        NrfDriverResources::with_ref_mut(|resources| f(resources.power.take().unwrap()))
-    }
+pub struct NrfPowerState {
+    power: POWER,
 }
 
-impl Initialized for NrfPowerDriver {
-    fn init(&self) {
-        self.0.ensure_is_initialized();
-        Self::with_dependency(|power| {
+impl TryDefault for NrfPowerState {
+    fn try_default() -> Result<Self, InitError> {
+        Self::with_dependency(|power: POWER| {
             // Enable the DC/DC converter
             power.dcdcen.write(|w| w.dcdcen().enabled());
-        });
+            Self { power }
+        })
     }
+}
 
-    fn is_initialized(&self) -> bool {
-        self.0.is_initialized()
+impl Teardown for NrfPowerState {
+    fn teardown(self) {
+        self.power.dcdcen.write(|w| w.dcdcen().disabled());
+        Self::return_dependency(self.power);
     }
 }
 
-impl Driver for NrfPowerDriver {}
+#[derive(Singleton)]
+#[singleton(content = NrfPowerState)]
+struct NrfPowerDriverState;
+
+// Drivers are initialized after logging is up, which is ensured here as all
+// of them depend on power.
+#[driver(state = NrfPowerDriverState, after(DefmtRttDriver))]
+pub struct NrfPowerDriver;
+
+impl NrfPowerDriver {
+    /// Whether VBUS is present so that the USB regulator can be powered.
+    pub(super) fn is_vbus_present(&self) -> bool {
+        NrfPowerDriverState::with_ref(|state| {
+            state
+                .power
+                .usbregstatus
+                .read()
+                .vbusdetect()
+                .is_vbus_present()
+        })
+    }
+
+    /// Whether the watchdog caused the last reset. Clears the reset reasons,
+    /// which otherwise accumulate until the next power-on reset.
+    pub(super) fn take_watchdog_reset(&self) -> bool {
+        NrfPowerDriverState::with_ref(|state| {
+            let reasons = state.power.resetreas.read();
+            // SAFETY: Writing the flags back clears them.
+            state
+                .power
+                .resetreas
+                .write(|w| unsafe { w.bits(reasons.bits()) });
+            reasons.dog().is_detected()
+        })
+    }
+}
 
 impl PowerDriver for NrfPowerDriver {}
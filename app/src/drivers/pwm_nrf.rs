@@ -0,0 +1,291 @@
+use super::api::pwm::*;
+use super::board_nrf as board;
+use super::power_nrf::NrfPowerDriver;
+use super::resources_nrf::NrfCeilingLock;
+use crate::buffers::{self, PWM_FADE_STEPS};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+use di::pool::PoolBox;
+use di::resources::{Composite, Owner};
+use di::singleton::{Singleton, Teardown, TryDefault};
+use di::{InitError, WithDependency};
+use di_macros::{driver, Dependency, Singleton};
+use nrf52840_hal::gpio::{Disconnected, Level, Output, Pin, PushPull};
+use nrf52840_hal::pac::{pwm0, PWM0, PWM1};
+
+/// 16 MHz / 1000 = 16 kHz, well above visible flicker.
+const LED_COUNTER_TOP: u16 = 1000;
+
+/// LED PWM periods per millisecond.
+const LED_PERIODS_PER_MS: u32 = 16;
+
+/// The buzzer PWM runs at 16 MHz / 8.
+const BUZZER_CLOCK_HZ: u32 = 2_000_000;
+
+/// Allowed range of COUNTERTOP.
+const COUNTER_TOP: core::ops::RangeInclusive<u32> = 3..=32767;
+
+/// Maximum value of SEQ[n].REFRESH.
+const MAX_REFRESH: u32 = 0xFF_FFFF;
+
+#[derive(Dependency)]
+#[dependency(owner = NrfPwmState)]
+struct NrfPwmLeds {
+    pwm0: PWM0,
+    red: board::LedRed,
+    green: board::LedGreen,
+    blue: board::LedBlue,
+}
+
+#[derive(Dependency)]
+#[dependency(owner = NrfPwmState)]
+struct NrfPwmBuzzer {
+    pwm1: PWM1,
+    buzzer: board::Buzzer,
+}
+
+pub struct NrfPwmState {
+    leds: PWM0,
+    buzzer: PWM1,
+    _pins: [Pin<Output<PushPull>>; 4],
+    /// One step per row, one compare value per LED channel.
+    sequence: PoolBox<[[u16; 4]; PWM_FADE_STEPS]>,
+    tone: PoolBox<[u16; 1]>,
+    color: Rgb,
+    fading: bool,
+    waker: Option<Waker>,
+}
+
+impl TryDefault for NrfPwmState {
+    fn try_default() -> Result<Self, InitError> {
+        Self::with_dependency(
+            |NrfPwmLeds {
+                 pwm0,
+                 red,
+                 green,
+                 blue,
+             }| {
+                Self::with_dependency(|NrfPwmBuzzer { pwm1, buzzer }| {
+                    // The LEDs are active low, keep them off.
+                    let off = |pin: Pin<Disconnected>| pin.into_push_pull_output(Level::High);
+                    let pins = [
+                        off(red.degrade()),
+                        off(green.degrade()),
+                        off(blue.degrade()),
+                        buzzer.degrade().into_push_pull_output(Level::Low),
+                    ];
+
+                    connect(&pwm0, &pins[..3]);
+                    pwm0.prescaler.write(|w| w.prescaler().div_1());
+                    pwm0.countertop
+                        .write(|w| unsafe { w.countertop().bits(LED_COUNTER_TOP) });
+                    pwm0.decoder
+                        .write(|w| w.load().individual().mode().refresh_count());
+                    pwm0.intenset.write(|w| w.seqend0().set());
+
+                    connect(&pwm1, &pins[3..]);
+                    pwm1.prescaler.write(|w| w.prescaler().div_8());
+                    pwm1.decoder
+                        .write(|w| w.load().common().mode().refresh_count());
+
+                    let mut state = Self {
+                        leds: pwm0,
+                        buzzer: pwm1,
+                        _pins: pins,
+                        sequence: buffers::PWM_FADE.alloc([[0; 4]; PWM_FADE_STEPS]).unwrap(),
+                        tone: buffers::PWM_TONE.alloc([0]).unwrap(),
+                        color: Rgb::OFF,
+                        fading: false,
+                        waker: None,
+                    };
+                    state.set_color(Rgb::OFF);
+                    state
+                })
+            },
+        )?
+    }
+}
+
+/// Routes the pins to the first channels of the PWM and enables it.
+fn connect(pwm: &pwm0::RegisterBlock, pins: &[Pin<Output<PushPull>>]) {
+    for (channel, pin) in pins.iter().enumerate() {
+        // SAFETY: Pin numbers are valid PSEL values.
+        pwm.psel.out[channel].write(|w| unsafe { w.bits(pin.psel_bits()) });
+    }
+    pwm.mode.write(|w| w.updown().up());
+    pwm.loop_.write(|w| w.cnt().disabled());
+    pwm.enable.write(|w| w.enable().enabled());
+}
+
+impl Teardown for NrfPwmState {
+    fn teardown(self) {
+        // The sequences go back to their pools, so the PWMs must not read
+        // them anymore.
+        disconnect(&self.leds);
+        disconnect(&self.buzzer);
+        self.leds.intenclr.write(|w| w.seqend0().clear());
+        let Self {
+            leds,
+            buzzer,
+            _pins: pins,
+            ..
+        } = self;
+        for pin in pins {
+            pin.into_disconnected();
+        }
+        // SAFETY: The pins taken from the board pins were dropped above.
+        let board::Pins {
+            led_red,
+            led_green,
+            led_blue,
+            buzzer: buzzer_pin,
+            ..
+        } = unsafe { board::Pins::steal() };
+        Self::return_dependency(
+            NrfPwmLeds {
+                pwm0: leds,
+                red: led_red,
+                green: led_green,
+                blue: led_blue,
+            }
+            .into_parts(),
+        );
+        Self::return_dependency(
+            NrfPwmBuzzer {
+                pwm1: buzzer,
+                buzzer: buzzer_pin,
+            }
+            .into_parts(),
+        );
+    }
+}
+
+/// Stops and disables the PWM and releases its pins.
+fn disconnect(pwm: &pwm0::RegisterBlock) {
+    pwm.tasks_stop.write(|w| unsafe { w.bits(1) });
+    pwm.enable.write(|w| w.enable().disabled());
+    for psel in pwm.psel.out.iter() {
+        psel.reset();
+    }
+}
+
+/// Plays `len` values of the sequence once. The PWM keeps the last value
+/// when the sequence has ended.
+fn play(pwm: &pwm0::RegisterBlock, sequence: *const u16, len: usize, refresh: u32) {
+    // SAFETY: Sequences are 'static and only rewritten by the next call.
+    pwm.seq0.ptr.write(|w| unsafe { w.bits(sequence as u32) });
+    pwm.seq0.cnt.write(|w| unsafe { w.cnt().bits(len as u16) });
+    pwm.seq0
+        .refresh
+        .write(|w| unsafe { w.cnt().bits(refresh.min(MAX_REFRESH)) });
+    pwm.seq0.enddelay.write(|w| unsafe { w.bits(0) });
+    pwm.tasks_seqstart[0].write(|w| unsafe { w.bits(1) });
+}
+
+/// With the polarity bit clear a channel is high until the counter
+/// reaches the compare value. The LED is lit for the rest of the period.
+fn compare(brightness: u8) -> u16 {
+    LED_COUNTER_TOP - (brightness as u32 * LED_COUNTER_TOP as u32 / u8::MAX as u32) as u16
+}
+
+fn step(color: Rgb) -> [u16; 4] {
+    [
+        compare(color.red),
+        compare(color.green),
+        compare(color.blue),
+        LED_COUNTER_TOP,
+    ]
+}
+
+fn interpolate(from: u8, to: u8, step: usize) -> u8 {
+    let from = from as i32;
+    let to = to as i32;
+    (from + (to - from) * (step as i32 + 1) / PWM_FADE_STEPS as i32) as u8
+}
+
+impl NrfPwmState {
+    fn set_color(&mut self, color: Rgb) {
+        self.sequence[0] = step(color);
+        self.color = color;
+        play(&self.leds, self.sequence.as_ptr().cast(), 4, 0);
+    }
+
+    fn start_fade(&mut self, color: Rgb, duration_ms: u32) {
+        let from = self.color;
+        for (i, step_values) in self.sequence.iter_mut().enumerate() {
+            *step_values = step(Rgb::new(
+                interpolate(from.red, color.red, i),
+                interpolate(from.green, color.green, i),
+                interpolate(from.blue, color.blue, i),
+            ));
+        }
+        self.color = color;
+        self.fading = true;
+        // Every step is repeated REFRESH + 1 times.
+        let periods = duration_ms.saturating_mul(LED_PERIODS_PER_MS) / PWM_FADE_STEPS as u32;
+        play(
+            &self.leds,
+            self.sequence.as_ptr().cast(),
+            4 * PWM_FADE_STEPS,
+            periods.saturating_sub(1),
+        );
+    }
+
+    fn tone(&mut self, frequency_hz: u32) {
+        if frequency_hz == 0 {
+            self.buzzer.tasks_stop.write(|w| unsafe { w.bits(1) });
+            return;
+        }
+        let top = (BUZZER_CLOCK_HZ / frequency_hz).clamp(*COUNTER_TOP.start(), *COUNTER_TOP.end());
+        self.buzzer
+            .countertop
+            .write(|w| unsafe { w.countertop().bits(top as u16) });
+        self.tone[0] = top as u16 / 2;
+        play(&self.buzzer, self.tone.as_ptr(), 1, 0);
+    }
+}
+
+/// Shared with the PWM interrupt handler which runs at priority 2.
+#[derive(Singleton)]
+#[singleton(content = NrfPwmState, lock = NrfCeilingLock<2>)]
+struct NrfPwmDriverState;
+
+#[driver(state = NrfPwmDriverState, depends_on(NrfPowerDriver))]
+pub struct NrfPwmDriver;
+
+impl PwmDriver for NrfPwmDriver {
+    fn set_color(&self, color: Rgb) {
+        NrfPwmDriverState::with_ref_mut(|state| state.set_color(color))
+    }
+
+    async fn fade_to(&self, color: Rgb, duration_ms: u32) {
+        NrfPwmDriverState::with_ref_mut(|state| state.start_fade(color, duration_ms));
+        poll_fn(|cx| {
+            NrfPwmDriverState::with_ref_mut(|state| {
+                if state.fading {
+                    state.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                } else {
+                    Poll::Ready(())
+                }
+            })
+        })
+        .await
+    }
+
+    fn tone(&self, frequency_hz: u32) {
+        NrfPwmDriverState::with_ref_mut(|state| state.tone(frequency_hz))
+    }
+
+    fn on_interrupt() {
+        NrfPwmDriverState::with_ref_mut(|state| {
+            if state.leds.events_seqend[0].read().bits() != 0 {
+                state.leds.events_seqend[0].reset();
+                state.fading = false;
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+        })
+    }
+}
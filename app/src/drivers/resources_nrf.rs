@@ -1,51 +1,189 @@
-use di::{
-    singleton::{Singleton, SingletonHolderImpl},
-    WithDependency,
-};
+use super::adc_nrf::NrfAdcState;
+use super::board_nrf as board;
+use super::flash_nrf::NrfFlashState;
+use super::gpio_nrf::NrfGpioState;
+use super::i2c_nrf::NrfI2cState;
+use super::mono_nrf_rtic::NrfMonoState;
+use super::osc_nrf::NrfOscState;
+use super::power_nrf::NrfPowerState;
+use super::pwm_nrf::NrfPwmState;
+use super::rng_nrf::NrfRngState;
+use super::spi_nrf::NrfSpiState;
+use super::uart_nrf::NrfUartState;
+use super::usb_nrf::NrfUsbState;
+use super::watchdog_nrf::NrfWatchdogState;
+use core::ops::Range;
+use di::lock::CeilingLock;
+use di::resources::Resources as _;
+use di::singleton::TryDefault;
+use di::{InitError, TryInitialized, WithDependency};
+use di_macros::{Resources, Singleton};
+use hal::gpio::{p0, p1};
 use hal::pac::*;
+use hal::ppi;
 use nrf52840_hal as hal;
+pub use nrf52840_hal::pac;
 
+#[derive(Resources)]
+#[resources(singleton = NrfDriverResources)]
 struct NrfResources {
-    pub power: Option<POWER>,
-    pub clock: Option<CLOCK>,
-    pub rng: Option<RNG>,
-    pub p0: Option<P0>,
-    pub rtc0: Option<RTC0>,
-    pub usbd: Option<USBD>,
+    #[claimed_by(NrfPowerState)]
+    power: Option<POWER>,
+    #[claimed_by(NrfOscState)]
+    clock: Option<CLOCK>,
+    #[claimed_by(NrfRngState)]
+    rng: Option<RNG>,
+    #[claimed_by(NrfGpioState)]
+    led: Option<board::Led>,
+    #[claimed_by(NrfGpioState)]
+    sensor_enable: Option<board::SensorEnable>,
+    #[claimed_by(NrfGpioState)]
+    gpiote: Option<GPIOTE>,
+    #[claimed_by(NrfGpioState)]
+    button: Option<board::Button>,
+    #[claimed_by(NrfGpioState)]
+    sensor_ready: Option<board::SensorReady>,
+    #[claimed_by(NrfI2cState)]
+    twim0: Option<TWIM0>,
+    #[claimed_by(NrfI2cState)]
+    i2c_scl: Option<board::I2cScl>,
+    #[claimed_by(NrfI2cState)]
+    i2c_sda: Option<board::I2cSda>,
+    #[claimed_by(NrfSpiState)]
+    spim2: Option<SPIM2>,
+    #[claimed_by(NrfSpiState)]
+    spi_sck: Option<board::SpiSck>,
+    #[claimed_by(NrfSpiState)]
+    spi_mosi: Option<board::SpiMosi>,
+    #[claimed_by(NrfSpiState)]
+    spi_miso: Option<board::SpiMiso>,
+    #[claimed_by(NrfSpiState)]
+    spi_cs_sensor: Option<board::SpiCsSensor>,
+    #[claimed_by(NrfSpiState)]
+    spi_cs_display: Option<board::SpiCsDisplay>,
+    #[claimed_by(NrfUartState)]
+    uarte0: Option<UARTE0>,
+    #[claimed_by(NrfUartState)]
+    uart_txd: Option<board::UartTxd>,
+    #[claimed_by(NrfUartState)]
+    uart_rxd: Option<board::UartRxd>,
+    #[claimed_by(NrfUartState)]
+    timer1: Option<TIMER1>,
+    #[claimed_by(NrfUartState)]
+    uart_idle_ppi: Option<ppi::Ppi0>,
+    #[claimed_by(NrfAdcState)]
+    saadc: Option<SAADC>,
+    #[claimed_by(NrfAdcState)]
+    adc_battery: Option<board::Battery>,
+    #[claimed_by(NrfPwmState)]
+    pwm0: Option<PWM0>,
+    #[claimed_by(NrfPwmState)]
+    led_red: Option<board::LedRed>,
+    #[claimed_by(NrfPwmState)]
+    led_green: Option<board::LedGreen>,
+    #[claimed_by(NrfPwmState)]
+    led_blue: Option<board::LedBlue>,
+    #[claimed_by(NrfPwmState)]
+    pwm1: Option<PWM1>,
+    #[claimed_by(NrfPwmState)]
+    buzzer: Option<board::Buzzer>,
+    #[claimed_by(NrfMonoState)]
+    rtc0: Option<RTC0>,
+    #[claimed_by(NrfUsbState)]
+    usbd: Option<USBD>,
+    #[claimed_by(NrfWatchdogState)]
+    wdt: Option<WDT>,
+    #[claimed_by(NrfFlashState)]
+    nvmc: Option<NVMC>,
 }
 
 impl WithDependency<Peripherals> for NrfResources {
-    fn with_dependency<Result, F: FnOnce(Peripherals) -> Result>(f: F) -> Result {
+    fn with_dependency<Result, F: FnOnce(Peripherals) -> Result>(
+        f: F,
+    ) -> core::result::Result<Result, InitError> {
         // SAFETY: The driver framework ensures that this is the unique
         // canonical provider for peripherals. It is assumed that the OS will
         // consume peripherals safely.
-        f(unsafe { Peripherals::steal() })
+        Ok(f(unsafe { Peripherals::steal() }))
     }
 }
 
-impl Default for NrfResources {
-    fn default() -> Self {
-        Self::with_dependency(|peripherals| NrfResources {
-            power: Some(peripherals.POWER),
-            clock: Some(peripherals.CLOCK),
-            rng: Some(peripherals.RNG),
-            p0: Some(peripherals.P0),
-            rtc0: Some(peripherals.RTC0),
-            usbd: Some(peripherals.USBD),
+impl TryDefault for NrfResources {
+    fn try_default() -> Result<Self, InitError> {
+        Self::with_dependency(|peripherals| {
+            let pins = board::Pins::new(
+                p0::Parts::new(peripherals.P0),
+                p1::Parts::new(peripherals.P1),
+            );
+            let ppi = ppi::Parts::new(peripherals.PPI);
+            NrfResources {
+                power: Some(peripherals.POWER),
+                clock: Some(peripherals.CLOCK),
+                rng: Some(peripherals.RNG),
+                led: Some(pins.led),
+                sensor_enable: Some(pins.sensor_enable),
+                gpiote: Some(peripherals.GPIOTE),
+                button: Some(pins.button),
+                sensor_ready: Some(pins.sensor_ready),
+                twim0: Some(peripherals.TWIM0),
+                i2c_scl: Some(pins.i2c_scl),
+                i2c_sda: Some(pins.i2c_sda),
+                spim2: Some(peripherals.SPIM2),
+                spi_sck: Some(pins.spi_sck),
+                spi_mosi: Some(pins.spi_mosi),
+                spi_miso: Some(pins.spi_miso),
+                spi_cs_sensor: Some(pins.spi_cs_sensor),
+                spi_cs_display: Some(pins.spi_cs_display),
+                uarte0: Some(peripherals.UARTE0),
+                uart_txd: Some(pins.uart_txd),
+                uart_rxd: Some(pins.uart_rxd),
+                timer1: Some(peripherals.TIMER1),
+                uart_idle_ppi: Some(ppi.ppi0),
+                saadc: Some(peripherals.SAADC),
+                adc_battery: Some(pins.battery),
+                pwm0: Some(peripherals.PWM0),
+                led_red: Some(pins.led_red),
+                led_green: Some(pins.led_green),
+                led_blue: Some(pins.led_blue),
+                pwm1: Some(peripherals.PWM1),
+                buzzer: Some(pins.buzzer),
+                rtc0: Some(peripherals.RTC0),
+                usbd: Some(peripherals.USBD),
+                wdt: Some(peripherals.WDT),
+                nvmc: Some(peripherals.NVMC),
+            }
         })
     }
 }
 
+#[derive(Singleton)]
+#[singleton(content = NrfResources)]
 pub struct NrfDriverResources;
 
-impl Singleton for NrfDriverResources {
-    type Content = NrfResources;
+/// Masks interrupts up to the given RTIC task priority only.
+pub type NrfCeilingLock<const CEILING: u8> = CeilingLock<CEILING, { pac::NVIC_PRIO_BITS }>;
 
-    fn with_state_holder<R, F>(f: F) -> R
-    where
-        F: FnOnce(&'static SingletonHolderImpl<NrfResources>) -> R,
-    {
-        static RESOURCES_HOLDER: SingletonHolderImpl<NrfResources> = SingletonHolderImpl::new();
-        f(&RESOURCES_HOLDER)
+/// Data RAM, the only memory EasyDMA can access.
+const DATA_RAM: Range<usize> = 0x2000_0000..0x2004_0000;
+
+/// Whether EasyDMA can transfer `bytes` directly.
+pub fn in_data_ram(bytes: &[u8]) -> bool {
+    let start = bytes.as_ptr() as usize;
+    DATA_RAM.contains(&start) && start + bytes.len() <= DATA_RAM.end
+}
+
+/// Takes ownership of the peripherals so that they can be handed out to
+/// drivers.
+pub fn init(_peripherals: Peripherals) -> Result<(), InitError> {
+    NrfDriverResources.try_init()?;
+
+    for ownership in NrfResources::OWNERSHIP {
+        defmt::debug!(
+            "{=str} is owned by {=str}",
+            ownership.peripheral,
+            ownership.owner
+        );
     }
+
+    Ok(())
 }
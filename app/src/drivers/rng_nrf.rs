@@ -1,65 +1,48 @@
 use super::api::rng::*;
-use super::api::Driver;
-use super::api::DriverStateHolder;
-use super::resources_nrf::NrfDriverResources;
-use di::singleton::Singleton;
-use di::Initialized;
-use di::WithDependency;
-use nrf52840_hal::{pac::RNG, Rng};
+use super::power_nrf::NrfPowerDriver;
+use super::resources_nrf::NrfCeilingLock;
+use di::resources::Owner;
+use di::singleton::{Singleton, Teardown, TryDefault};
+use di::{InitError, WithDependency};
+use di_macros::{driver, Singleton};
+use nrf52840_hal::pac::Peripherals;
+use nrf52840_hal::Rng;
 use rand_core::CryptoRng;
 
 pub struct NrfRngState {
     rng: Rng,
 }
 
-impl WithDependency<RNG> for NrfRngState {
-    fn with_dependency<Result, F: FnOnce(RNG) -> Result>(f: F) -> Result {
-        NrfDriverResources::with_ref_mut(|resources| f(resources.rng.take().unwrap()))
+impl TryDefault for NrfRngState {
+    fn try_default() -> Result<Self, InitError> {
+        Self::with_dependency(|rng| Self { rng: Rng::new(rng) })
     }
 }
 
-impl Default for NrfRngState {
-    fn default() -> NrfRngState {
-        Self::with_dependency(|rng| Self { rng: Rng::new(rng) })
+impl Teardown for NrfRngState {
+    fn teardown(self) {
+        drop(self.rng);
+        // SAFETY: The dropped driver owned the only instance of the peripheral.
+        let rng = unsafe { Peripherals::steal() }.RNG;
+        Self::return_dependency(rng);
     }
 }
 
+#[derive(Singleton)]
+#[singleton(content = NrfRngState, lock = NrfCeilingLock<1>)]
 struct NrfRngDriverState;
 
-impl Singleton for NrfRngDriverState {
-    type Content = NrfRngState;
-
-    fn with_state_holder<Result, F>(f: F) -> Result
-    where
-        F: FnOnce(&DriverStateHolder<Self::Content>) -> Result,
-    {
-        static DRIVER_STATE: DriverStateHolder<NrfRngState> = DriverStateHolder::new();
-        f(&DRIVER_STATE)
-    }
-}
-
-struct NrfRngDriver;
+#[driver(state = NrfRngDriverState, depends_on(NrfPowerDriver))]
+pub struct NrfRngDriver;
 
 impl NrfRngDriver {
-    const fn new() -> NrfRngDriver {
+    pub const fn new() -> NrfRngDriver {
         NrfRngDriver
     }
 }
 
-impl Initialized for NrfRngDriver {
-    fn init(&self) {
-        NrfRngDriverState.init();
-    }
-
-    fn is_initialized(&self) -> bool {
-        NrfRngDriverState.is_initialized()
-    }
-}
-
 impl CryptoRng for NrfRngDriver {}
 
-impl Driver for NrfRngDriver {}
-
 impl RngDriver for NrfRngDriver {
     fn next(&self, dest: &mut [u8]) {
         NrfRngDriverState::with_ref_mut(|state| state.rng.random(dest))
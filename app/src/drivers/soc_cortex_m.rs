@@ -1,5 +1,7 @@
-use super::api::{soc::SocDriver, Driver, StatelessDriver};
-use di::Initialized;
+use super::api::soc::SocDriver;
+use cortex_m::peripheral::DWT;
+use di_macros::driver;
+use panic_probe as _;
 
 // same panicking *behavior* as `panic-probe` but doesn't print a panic message
 // this prevents the panic message being printed *twice* when `defmt::panic` is invoked
@@ -8,19 +10,23 @@ fn panic() -> ! {
     cortex_m::asm::udf()
 }
 
-struct SocCortexMDriver(StatelessDriver);
+#[driver]
+pub struct SocCortexMDriver;
 
-impl Initialized for SocCortexMDriver {
-    fn init(&self) {
-        self.0.init();
-        use panic_probe as _;
+impl SocCortexMDriver {
+    /// Starts the cycle counter. Called before any driver is initialized,
+    /// this driver included.
+    pub fn start_cycle_counter() {
+        // SAFETY: Tracing and the cycle counter are not used anywhere else.
+        let mut core = unsafe { cortex_m::Peripherals::steal() };
+        core.DCB.enable_trace();
+        core.DWT.enable_cycle_counter();
     }
 
-    fn is_initialized(&self) -> bool {
-        self.0.is_initialized()
+    /// Free-running CPU cycle counter.
+    pub fn cycles() -> u32 {
+        DWT::cycle_count()
     }
 }
 
-impl Driver for SocCortexMDriver {}
-
 impl SocDriver for SocCortexMDriver {}
@@ -0,0 +1,382 @@
+use super::api::{self, spi::*};
+use super::board_nrf as board;
+use super::power_nrf::NrfPowerDriver;
+use super::resources_nrf::{in_data_ram, NrfCeilingLock};
+use crate::buffers::{self, SPI_DMA_BUFFER_SIZE};
+use core::future::poll_fn;
+use core::ops::Range;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::{Poll, Waker};
+use di::dma;
+use di::pool::PoolBox;
+use di::resources::{Composite, Owner};
+use di::singleton::{Singleton, Teardown, TryDefault};
+use di::{InitError, TryInitialized, WithDependency};
+use di_macros::{driver, Dependency, Singleton};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{ErrorType, Operation, SpiBus, SpiDevice, MODE_0};
+use nrf52840_hal::gpio::{Disconnected, Level, Output, Pin, PushPull};
+use nrf52840_hal::pac::{spim0, SPIM2};
+use nrf52840_hal::spim::{self, Frequency, Spim};
+
+const FREQUENCY: Frequency = Frequency::M8;
+const CYCLES_PER_MICROSECOND: u64 = 64;
+
+/// Largest TXD.MAXCNT and RXD.MAXCNT.
+const MAX_DMA_LEN: usize = u16::MAX as usize;
+
+/// Polls of the STOPPED event, well over the 64 CPU cycles a byte takes at
+/// 8 MHz.
+const STOP_POLLS: u32 = 1_000;
+
+#[derive(Dependency)]
+#[dependency(owner = NrfSpiState)]
+struct NrfSpiResources {
+    spim2: SPIM2,
+    sck: board::SpiSck,
+    mosi: board::SpiMosi,
+    miso: board::SpiMiso,
+}
+
+#[derive(Dependency)]
+#[dependency(owner = NrfSpiState)]
+struct NrfSpiChipSelects {
+    sensor: board::SpiCsSensor,
+    display: board::SpiCsDisplay,
+}
+
+pub struct NrfSpiState {
+    spim: Spim<SPIM2>,
+    chip_selects: [Pin<Output<PushPull>>; 2],
+    dma: PoolBox<[u8; SPI_DMA_BUFFER_SIZE]>,
+}
+
+impl TryDefault for NrfSpiState {
+    fn try_default() -> Result<Self, InitError> {
+        Self::with_dependency(|bus: NrfSpiResources| {
+            Self::with_dependency(|chip_selects: NrfSpiChipSelects| {
+                let pins = spim::Pins {
+                    sck: Some(bus.sck.into_push_pull_output(Level::Low).degrade()),
+                    mosi: Some(bus.mosi.into_push_pull_output(Level::Low).degrade()),
+                    miso: Some(bus.miso.into_floating_input().degrade()),
+                };
+                let deselected = |pin: Pin<Disconnected>| pin.into_push_pull_output(Level::High);
+                Self {
+                    spim: Spim::new(bus.spim2, pins, FREQUENCY, MODE_0, 0),
+                    chip_selects: [
+                        deselected(chip_selects.sensor.degrade()),
+                        deselected(chip_selects.display.degrade()),
+                    ],
+                    dma: buffers::SPI_DMA.alloc_zeroed().unwrap(),
+                }
+            })
+        })?
+    }
+}
+
+impl Teardown for NrfSpiState {
+    fn teardown(self) {
+        let _ = NrfSpiWaker::deinit();
+        let (spim2, spim::Pins { sck, mosi, miso }) = self.spim.free();
+        spim2.enable.write(|w| w.enable().disabled());
+        if let Some(sck) = sck {
+            sck.into_disconnected();
+        }
+        if let Some(mosi) = mosi {
+            mosi.into_disconnected();
+        }
+        if let Some(miso) = miso {
+            miso.into_disconnected();
+        }
+        for cs in self.chip_selects {
+            cs.into_disconnected();
+        }
+        // SAFETY: The pins taken from the board pins were dropped above.
+        let pins = unsafe { board::Pins::steal() };
+        Self::return_dependency(
+            NrfSpiResources {
+                spim2,
+                sck: pins.spi_sck,
+                mosi: pins.spi_mosi,
+                miso: pins.spi_miso,
+            }
+            .into_parts(),
+        );
+        Self::return_dependency(
+            NrfSpiChipSelects {
+                sensor: pins.spi_cs_sensor,
+                display: pins.spi_cs_display,
+            }
+            .into_parts(),
+        );
+    }
+}
+
+/// Splits a transfer of `read` and `write` bytes into pieces EasyDMA can
+/// handle. Writes that go through the bounce buffer are limited to its size.
+fn chunks(
+    read: usize,
+    write: usize,
+    bounce: bool,
+) -> impl Iterator<Item = (Range<usize>, Range<usize>)> {
+    let size = if bounce {
+        SPI_DMA_BUFFER_SIZE
+    } else {
+        MAX_DMA_LEN
+    };
+    dma::chunks(read, write, size)
+}
+
+fn delay(ns: u32) {
+    let cycles = (ns as u64 * CYCLES_PER_MICROSECOND).div_ceil(1000);
+    cortex_m::asm::delay(cycles as u32);
+}
+
+impl NrfSpiState {
+    fn transaction(
+        &mut self,
+        chip_select: board::SpiChipSelect,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), spim::Error> {
+        let cs = chip_select as usize;
+        self.chip_selects[cs].set_low().unwrap();
+        let result = operations
+            .iter_mut()
+            .try_for_each(|operation| self.run(operation))
+            .and_then(|()| SpiBus::flush(&mut self.spim));
+        self.chip_selects[cs].set_high().unwrap();
+        result
+    }
+
+    fn run(&mut self, operation: &mut Operation<'_, u8>) -> Result<(), spim::Error> {
+        match operation {
+            Operation::Read(words) => self.transfer(words, &[]),
+            Operation::Write(words) => self.transfer(&mut [], words),
+            Operation::Transfer(read, write) => self.transfer(read, write),
+            Operation::TransferInPlace(words) => SpiBus::transfer_in_place(&mut self.spim, words),
+            Operation::DelayNs(ns) => {
+                delay(*ns);
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes from flash go through the DMA buffer.
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), spim::Error> {
+        let bounce = !write.is_empty() && !in_data_ram(write);
+        for (rx, tx) in chunks(read.len(), write.len(), bounce) {
+            let write = if bounce {
+                let dma = &mut self.dma[..tx.len()];
+                dma.copy_from_slice(&write[tx]);
+                &*dma
+            } else {
+                &write[tx]
+            };
+            let read = &mut read[rx];
+            match (read.is_empty(), write.is_empty()) {
+                (true, _) => SpiBus::write(&mut self.spim, write)?,
+                (false, true) => SpiBus::read(&mut self.spim, read)?,
+                (false, false) => SpiBus::transfer(&mut self.spim, read, write)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Waker of the task waiting for a transfer, shared with the SPIM interrupt
+/// handler which runs at priority 2.
+#[derive(Singleton)]
+#[singleton(content = Option<Waker>, lock = NrfCeilingLock<2>)]
+struct NrfSpiWaker;
+
+/// Locked for a whole transaction, so no other device is selected while the
+/// transfers of an async transaction are in flight.
+#[derive(Singleton)]
+#[singleton(content = NrfSpiState, lock = NrfCeilingLock<1>)]
+struct NrfSpiDriverState;
+
+#[driver(state = NrfSpiDriverState, init = Self::start, depends_on(NrfPowerDriver))]
+pub struct NrfSpiDriver;
+
+impl NrfSpiDriver {
+    fn start(&self) -> Result<(), InitError> {
+        NrfSpiWaker.try_init()
+    }
+}
+
+impl SpiDriver for NrfSpiDriver {
+    type ChipSelect = board::SpiChipSelect;
+    type Device = NrfSpiDevice;
+
+    fn device(&self, chip_select: board::SpiChipSelect) -> NrfSpiDevice {
+        NrfSpiDevice { chip_select }
+    }
+
+    fn on_interrupt() {
+        // SAFETY: Only the interrupt is disabled, the waiting task handles
+        // the event.
+        let spim = unsafe { &*SPIM2::ptr() };
+        spim.intenclr.write(|w| w.end().clear());
+        NrfSpiWaker::with_ref_mut(|waker| {
+            if let Some(waker) = waker.take() {
+                waker.wake();
+            }
+        })
+    }
+}
+
+/// Selects its device for the duration of each transaction.
+pub struct NrfSpiDevice {
+    chip_select: board::SpiChipSelect,
+}
+
+impl ErrorType for NrfSpiDevice {
+    type Error = api::BusError<spim::Error>;
+}
+
+impl SpiDevice for NrfSpiDevice {
+    /// Busy waits for the transfers. Fails with `Busy` while an async
+    /// transaction holds the bus.
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        NrfSpiDriverState::try_with_ref_mut(|state| {
+            state.transaction(self.chip_select, operations)
+        })?
+        .map_err(api::BusError::Bus)
+    }
+}
+
+impl embedded_hal_async::spi::SpiDevice for NrfSpiDevice {
+    /// Sleeps until the SPIM signals the end of each transfer. Delays are
+    /// short and busy waited.
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        let mut state = NrfSpiDriverState::lock().await?;
+        // SAFETY: The locked state owns the SPIM, the HAL is bypassed for
+        // the async transfers only.
+        let spim = unsafe { &*SPIM2::ptr() };
+        let mut transfer = Transfer {
+            spim,
+            state: &mut state,
+            chip_select: self.chip_select as usize,
+            running: false,
+        };
+        transfer.state.chip_selects[transfer.chip_select]
+            .set_low()
+            .unwrap();
+        for operation in operations {
+            transfer.run(operation).await;
+        }
+        Ok(())
+    }
+}
+
+/// Transfers of an async transaction. Stops the SPIM if the transaction is
+/// cancelled, so EasyDMA does not touch the buffers after they are gone, and
+/// deselects the device in any case.
+struct Transfer<'a> {
+    spim: &'a spim0::RegisterBlock,
+    state: &'a mut NrfSpiState,
+    chip_select: usize,
+    running: bool,
+}
+
+impl Transfer<'_> {
+    async fn run(&mut self, operation: &mut Operation<'_, u8>) {
+        match operation {
+            Operation::Read(words) => self.transfer(words, &[]).await,
+            Operation::Write(words) => self.transfer(&mut [], words).await,
+            Operation::Transfer(read, write) => self.transfer(read, write).await,
+            Operation::TransferInPlace(words) => {
+                for start in (0..words.len()).step_by(MAX_DMA_LEN) {
+                    let chunk = &mut words[start..(start + MAX_DMA_LEN).min(words.len())];
+                    let (ptr, len) = (chunk.as_mut_ptr(), chunk.len());
+                    self.dma(ptr, len, ptr, len).await;
+                }
+            }
+            Operation::DelayNs(ns) => delay(*ns),
+        }
+    }
+
+    /// Writes from flash go through the DMA buffer.
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) {
+        let bounce = !write.is_empty() && !in_data_ram(write);
+        for (rx, tx) in chunks(read.len(), write.len(), bounce) {
+            let (tx_ptr, tx_len) = if bounce {
+                let dma = &mut self.state.dma[..tx.len()];
+                dma.copy_from_slice(&write[tx]);
+                (dma.as_ptr(), dma.len())
+            } else {
+                (write[tx.clone()].as_ptr(), tx.len())
+            };
+            let read = &mut read[rx];
+            self.dma(read.as_mut_ptr(), read.len(), tx_ptr, tx_len)
+                .await;
+        }
+    }
+
+    /// Runs a single transfer and waits for its END event. The SPIM clocks
+    /// out the ORC byte once the write buffer is exhausted.
+    async fn dma(&mut self, rx: *mut u8, rx_len: usize, tx: *const u8, tx_len: usize) {
+        let spim = self.spim;
+        // SAFETY: The buffers are in RAM, no longer than MAXCNT allows and
+        // outlive the transfer, see Drop.
+        spim.rxd.ptr.write(|w| unsafe { w.ptr().bits(rx as u32) });
+        spim.rxd
+            .maxcnt
+            .write(|w| unsafe { w.maxcnt().bits(rx_len as u16) });
+        spim.txd.ptr.write(|w| unsafe { w.ptr().bits(tx as u32) });
+        spim.txd
+            .maxcnt
+            .write(|w| unsafe { w.maxcnt().bits(tx_len as u16) });
+        spim.events_end.reset();
+        // The write buffer must be written before EasyDMA reads it.
+        compiler_fence(Ordering::SeqCst);
+        // SAFETY: Triggering a task has no preconditions.
+        spim.tasks_start.write(|w| unsafe { w.bits(1) });
+        self.running = true;
+        poll_fn(|cx| {
+            NrfSpiWaker::with_ref_mut(|waker| *waker = Some(cx.waker().clone()));
+            if spim.events_end.read().bits() != 0 {
+                Poll::Ready(())
+            } else {
+                spim.intenset.write(|w| w.end().set());
+                Poll::Pending
+            }
+        })
+        .await;
+        self.running = false;
+        // EasyDMA has written the read buffer before the event.
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+/// Stops a cancelled transfer. The SPIM stops after the byte on the wire,
+/// if it does not within `STOP_POLLS` it is disabled and enabled again.
+///
+/// Either way the SPIM is left enabled and idle as the HAL expects it, with
+/// its interrupts disabled and END cleared. The device has received part of
+/// the transfer, which it drops when its chip select goes high.
+impl Drop for Transfer<'_> {
+    fn drop(&mut self) {
+        let spim = self.spim;
+        spim.intenclr.write(|w| w.end().clear());
+        if self.running {
+            spim.events_stopped.reset();
+            // SAFETY: Triggering a task has no preconditions.
+            spim.tasks_stop.write(|w| unsafe { w.bits(1) });
+            let stopped = (0..STOP_POLLS).any(|_| spim.events_stopped.read().bits() != 0);
+            if !stopped {
+                defmt::warn!("SPI: the transfer did not stop, resetting the SPIM.");
+                spim.enable.write(|w| w.enable().disabled());
+                spim.enable.write(|w| w.enable().enabled());
+            }
+            spim.events_stopped.reset();
+        }
+        spim.events_end.reset();
+        self.state.chip_selects[self.chip_select]
+            .set_high()
+            .unwrap();
+    }
+}
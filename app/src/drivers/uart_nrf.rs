@@ -0,0 +1,473 @@
+use super::api::uart::*;
+use super::board_nrf as board;
+use super::power_nrf::NrfPowerDriver;
+use super::resources_nrf::NrfCeilingLock;
+use crate::buffers::{self, UART_RX_BUFFER_SIZE, UART_TX_BUFFER_SIZE};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+use di::pool::PoolBox;
+use di::resources::{Composite, Owner};
+use di::singleton::{Singleton, Teardown, TryDefault};
+use di::{InitError, WithDependency};
+use di_macros::{driver, Dependency, Singleton};
+use embedded_io::{ErrorKind, ErrorType};
+use heapless::Deque;
+use nrf52840_hal::gpio::Level;
+use nrf52840_hal::pac::{TIMER1, UARTE0};
+use nrf52840_hal::ppi::{ConfigurablePpi, Ppi, Ppi0};
+
+const RX_FIFO_SIZE: usize = 256;
+
+/// Frames that have been received but not read completely.
+const MAX_FRAMES: usize = 16;
+
+/// 3.5 characters at 9600 baud, the Modbus RTU frame gap, in microseconds.
+const IDLE_TIMEOUT_US: u32 = 4_000;
+
+/// Polls of the stop events, a few characters at 9600 baud.
+const STOP_POLLS: u32 = 100_000;
+
+#[derive(Dependency)]
+#[dependency(owner = NrfUartState)]
+struct NrfUartResources {
+    uarte0: UARTE0,
+    txd: board::UartTxd,
+    rxd: board::UartRxd,
+}
+
+/// Idle-line detection: every received byte restarts TIMER1 through a PPI
+/// channel, a timeout stops reception which flushes the partially filled
+/// buffer.
+#[derive(Dependency)]
+#[dependency(owner = NrfUartState)]
+struct NrfUartIdleTimer {
+    timer1: TIMER1,
+    ppi: Ppi0,
+}
+
+pub struct NrfUartState {
+    uarte: UARTE0,
+    timer: TIMER1,
+    ppi: Ppi0,
+    rx_buffers: [PoolBox<[u8; UART_RX_BUFFER_SIZE]>; 2],
+    tx_buffer: PoolBox<[u8; UART_TX_BUFFER_SIZE]>,
+    /// Buffer that EasyDMA is currently receiving into.
+    active: usize,
+    rx: Deque<u8, RX_FIFO_SIZE>,
+    overrun: bool,
+    /// Lengths of the frames in `rx`, the first one may have been read
+    /// partially.
+    frames: Deque<usize, MAX_FRAMES>,
+    /// Bytes in `rx` that were received since the line last went idle.
+    pending: usize,
+    /// Reception is being stopped on an idle line.
+    stopping: bool,
+    flushing: bool,
+    tx_busy: bool,
+    rx_waker: Option<Waker>,
+    tx_waker: Option<Waker>,
+}
+
+impl TryDefault for NrfUartState {
+    fn try_default() -> Result<Self, InitError> {
+        Self::with_dependency(|NrfUartResources { uarte0, txd, rxd }| {
+            Self::with_dependency(|NrfUartIdleTimer { timer1, mut ppi }| {
+                let txd = txd.into_push_pull_output(Level::High).degrade();
+                let rxd = rxd.into_floating_input().degrade();
+                // SAFETY: Pin numbers are valid PSEL values.
+                uarte0
+                    .psel
+                    .txd
+                    .write(|w| unsafe { w.bits(txd.psel_bits()) });
+                uarte0
+                    .psel
+                    .rxd
+                    .write(|w| unsafe { w.bits(rxd.psel_bits()) });
+                uarte0.baudrate.write(|w| w.baudrate().baud9600());
+                uarte0
+                    .config
+                    .write(|w| w.hwfc().disabled().parity().excluded());
+                uarte0.enable.write(|w| w.enable().enabled());
+
+                timer1.mode.write(|w| w.mode().timer());
+                timer1.bitmode.write(|w| w.bitmode()._32bit());
+                // SAFETY: 16 MHz / 2^4 = 1 MHz
+                timer1.prescaler.write(|w| unsafe { w.prescaler().bits(4) });
+                timer1.cc[0].write(|w| unsafe { w.bits(IDLE_TIMEOUT_US) });
+                timer1.shorts.write(|w| w.compare0_stop().enabled());
+                timer1.intenset.write(|w| w.compare0().set());
+
+                ppi.set_event_endpoint(&uarte0.events_rxdrdy);
+                ppi.set_task_endpoint(&timer1.tasks_clear);
+                ppi.set_fork_task_endpoint(&timer1.tasks_start);
+                ppi.enable();
+
+                // Reception continues into the next buffer without a gap,
+                // its pointer is set as soon as the current one is in use.
+                uarte0.shorts.write(|w| w.endrx_startrx().enabled());
+                uarte0
+                    .intenset
+                    .write(|w| w.endrx().set().rxstarted().set().rxto().set().endtx().set());
+
+                let mut state = Self {
+                    uarte: uarte0,
+                    timer: timer1,
+                    ppi,
+                    rx_buffers: [
+                        buffers::UART_RX.alloc_zeroed().unwrap(),
+                        buffers::UART_RX.alloc_zeroed().unwrap(),
+                    ],
+                    tx_buffer: buffers::UART_TX.alloc_zeroed().unwrap(),
+                    active: 0,
+                    rx: Deque::new(),
+                    overrun: false,
+                    frames: Deque::new(),
+                    pending: 0,
+                    stopping: false,
+                    flushing: false,
+                    tx_busy: false,
+                    rx_waker: None,
+                    tx_waker: None,
+                };
+                state.start_rx();
+                state
+            })
+        })?
+    }
+}
+
+impl Teardown for NrfUartState {
+    fn teardown(self) {
+        // The buffers go back to their pools, so EasyDMA must have stopped.
+        let uarte = &self.uarte;
+        uarte.intenclr.write(|w| unsafe { w.bits(u32::MAX) });
+        uarte.shorts.reset();
+        self.timer.intenclr.write(|w| w.compare0().clear());
+        self.timer.tasks_stop.write(|w| unsafe { w.bits(1) });
+        uarte.tasks_stoprx.write(|w| unsafe { w.bits(1) });
+        uarte.tasks_stoptx.write(|w| unsafe { w.bits(1) });
+        let stopped = || {
+            uarte.events_rxto.read().bits() != 0
+                && (!self.tx_busy || uarte.events_txstopped.read().bits() != 0)
+        };
+        if !(0..STOP_POLLS).any(|_| stopped()) {
+            defmt::warn!("UARTE did not stop");
+        }
+        uarte.enable.write(|w| w.enable().disabled());
+        uarte.psel.txd.reset();
+        uarte.psel.rxd.reset();
+
+        let Self {
+            uarte,
+            timer,
+            mut ppi,
+            ..
+        } = self;
+        ppi.disable();
+        // SAFETY: The pins taken from the board pins were dropped when the
+        // UARTE was set up, it only kept their numbers.
+        let board::Pins {
+            uart_txd, uart_rxd, ..
+        } = unsafe { board::Pins::steal() };
+        Self::return_dependency(
+            NrfUartResources {
+                uarte0: uarte,
+                txd: uart_txd.into_disconnected(),
+                rxd: uart_rxd.into_disconnected(),
+            }
+            .into_parts(),
+        );
+        Self::return_dependency(NrfUartIdleTimer { timer1: timer, ppi }.into_parts());
+    }
+}
+
+impl NrfUartState {
+    fn set_rx_buffer(&mut self, index: usize) {
+        let buffer = &mut self.rx_buffers[index];
+        // SAFETY: The buffer is 'static and only read after its ENDRX.
+        self.uarte
+            .rxd
+            .ptr
+            .write(|w| unsafe { w.ptr().bits(buffer.as_mut_ptr() as u32) });
+        self.uarte
+            .rxd
+            .maxcnt
+            .write(|w| unsafe { w.maxcnt().bits(UART_RX_BUFFER_SIZE as u16) });
+    }
+
+    fn start_rx(&mut self) {
+        self.set_rx_buffer(self.active);
+        self.uarte.tasks_startrx.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// The pointer register is double buffered, the next buffer is queued
+    /// while EasyDMA receives into the active one.
+    fn on_rx_started(&mut self) {
+        self.set_rx_buffer(1 - self.active);
+    }
+
+    /// Drains the buffer that has just been filled. The short has already
+    /// restarted reception into the other one, unless reception is being
+    /// stopped on an idle line. It is restarted once the UARTE is flushed.
+    fn on_end_rx(&mut self) {
+        let filled = self.active;
+        let amount = self.uarte.rxd.amount.read().amount().bits() as usize;
+        self.active = 1 - self.active;
+        for &byte in &self.rx_buffers[filled][..amount] {
+            if self.rx.push_back(byte).is_ok() {
+                self.pending += 1;
+            } else {
+                self.overrun = true;
+            }
+        }
+        if core::mem::take(&mut self.flushing) {
+            self.end_frame();
+            self.stopping = false;
+            self.uarte.shorts.write(|w| w.endrx_startrx().enabled());
+            self.start_rx();
+        }
+        self.wake_rx();
+    }
+
+    /// The line went idle, reception is stopped to get hold of the bytes in
+    /// the partially filled buffer.
+    fn on_idle(&mut self) {
+        if self.stopping {
+            return;
+        }
+        self.stopping = true;
+        self.uarte.shorts.reset();
+        self.uarte.tasks_stoprx.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Reception has stopped, the UARTE may still hold a few bytes that are
+    /// moved to the next buffer.
+    fn on_rx_timeout(&mut self) {
+        self.flushing = true;
+        self.set_rx_buffer(self.active);
+        self.uarte.tasks_flushrx.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Queues the bytes received since the line last went idle as a frame.
+    /// Frames are merged when too many of them are waiting.
+    fn end_frame(&mut self) {
+        let len = core::mem::take(&mut self.pending);
+        if len == 0 {
+            return;
+        }
+        if let Err(len) = self.frames.push_back(len) {
+            *self.frames.back_mut().unwrap() += len;
+            self.overrun = true;
+        }
+    }
+
+    fn wake_rx(&mut self) {
+        if let Some(waker) = self.rx_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+        if core::mem::take(&mut self.overrun) {
+            return Err(ErrorKind::OutOfMemory);
+        }
+        let mut read = 0;
+        while read < buf.len() {
+            let Some(byte) = self.rx.pop_front() else {
+                break;
+            };
+            buf[read] = byte;
+            read += 1;
+        }
+        // The bytes are taken from the oldest frames first.
+        let mut consumed = read;
+        while consumed > 0 {
+            match self.frames.front_mut() {
+                Some(len) if *len <= consumed => {
+                    consumed -= *len;
+                    self.frames.pop_front();
+                }
+                Some(len) => {
+                    *len -= consumed;
+                    consumed = 0;
+                }
+                None => {
+                    self.pending -= consumed;
+                    consumed = 0;
+                }
+            }
+        }
+        Ok(read)
+    }
+
+    /// Returns the number of bytes that are being sent.
+    fn start_tx(&mut self, buf: &[u8]) -> usize {
+        let len = buf.len().min(UART_TX_BUFFER_SIZE);
+        self.tx_buffer[..len].copy_from_slice(&buf[..len]);
+        // SAFETY: The buffer is 'static and not touched until ENDTX.
+        self.uarte
+            .txd
+            .ptr
+            .write(|w| unsafe { w.ptr().bits(self.tx_buffer.as_ptr() as u32) });
+        self.uarte
+            .txd
+            .maxcnt
+            .write(|w| unsafe { w.maxcnt().bits(len as u16) });
+        self.tx_busy = true;
+        self.uarte.tasks_starttx.write(|w| unsafe { w.bits(1) });
+        len
+    }
+}
+
+/// Shared with the UARTE and idle timer interrupt handlers, which run at
+/// priority 2.
+#[derive(Singleton)]
+#[singleton(content = NrfUartState, lock = NrfCeilingLock<2>)]
+struct NrfUartDriverState;
+
+#[driver(state = NrfUartDriverState, depends_on(NrfPowerDriver))]
+pub struct NrfUartDriver;
+
+impl UartDriver for NrfUartDriver {
+    type Port = NrfUartPort;
+
+    fn port(&self) -> NrfUartPort {
+        NrfUartPort
+    }
+
+    fn on_interrupt() {
+        NrfUartDriverState::with_ref_mut(|state| {
+            // ENDRX goes first, RXSTARTED hands the drained buffer back.
+            let uarte = &state.uarte;
+            if uarte.events_endrx.read().bits() != 0 {
+                uarte.events_endrx.reset();
+                state.on_end_rx();
+            }
+            let uarte = &state.uarte;
+            if uarte.events_rxstarted.read().bits() != 0 {
+                uarte.events_rxstarted.reset();
+                state.on_rx_started();
+            }
+            let timer = &state.timer;
+            if timer.events_compare[0].read().bits() != 0 {
+                timer.events_compare[0].reset();
+                state.on_idle();
+            }
+            let uarte = &state.uarte;
+            if uarte.events_rxto.read().bits() != 0 {
+                uarte.events_rxto.reset();
+                state.on_rx_timeout();
+            }
+            let uarte = &state.uarte;
+            if uarte.events_endtx.read().bits() != 0 {
+                uarte.events_endtx.reset();
+                state.tx_busy = false;
+                if let Some(waker) = state.tx_waker.take() {
+                    waker.wake();
+                }
+            }
+        })
+    }
+}
+
+pub struct NrfUartPort;
+
+impl NrfUartPort {
+    fn tx_busy() -> bool {
+        NrfUartDriverState::with_ref(|state| state.tx_busy)
+    }
+}
+
+impl ErrorType for NrfUartPort {
+    type Error = ErrorKind;
+}
+
+impl embedded_io::Read for NrfUartPort {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+        loop {
+            let read = NrfUartDriverState::with_ref_mut(|state| state.read(buf))?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl embedded_io::Write for NrfUartPort {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.flush()?;
+        Ok(NrfUartDriverState::with_ref_mut(|state| {
+            state.start_tx(buf)
+        }))
+    }
+
+    fn flush(&mut self) -> Result<(), ErrorKind> {
+        while Self::tx_busy() {
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+}
+
+impl embedded_io_async::Read for NrfUartPort {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+        poll_fn(|cx| {
+            NrfUartDriverState::with_ref_mut(|state| match state.read(buf) {
+                Ok(0) if !buf.is_empty() => {
+                    state.rx_waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+                result => Poll::Ready(result),
+            })
+        })
+        .await
+    }
+}
+
+impl embedded_io_async::Write for NrfUartPort {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        embedded_io_async::Write::flush(self).await?;
+        Ok(NrfUartDriverState::with_ref_mut(|state| {
+            state.start_tx(buf)
+        }))
+    }
+
+    async fn flush(&mut self) -> Result<(), ErrorKind> {
+        poll_fn(|cx| {
+            NrfUartDriverState::with_ref_mut(|state| {
+                if state.tx_busy {
+                    state.tx_waker = Some(cx.waker().clone());
+                    Poll::Pending
+                } else {
+                    Poll::Ready(Ok(()))
+                }
+            })
+        })
+        .await
+    }
+}
+
+impl UartPort for NrfUartPort {
+    async fn read_frame(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+        poll_fn(|cx| {
+            NrfUartDriverState::with_ref_mut(|state| match state.frames.front() {
+                Some(&len) => {
+                    let len = len.min(buf.len());
+                    Poll::Ready(state.read(&mut buf[..len]))
+                }
+                None => {
+                    state.rx_waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+}
@@ -1,74 +1,90 @@
-use super::api::Driver;
-use super::api::{usb::*, DriverStateHolder};
+use super::api::{self, osc::OscillatorDriver, usb::*, Driver};
 use super::osc_nrf::{DontCare, NrfHighAccOscToken, NrfHighAccOscillatorDriver};
-use super::resources_nrf::NrfDriverResources;
-use super::OscillatorDriver;
-use di::singleton::Singleton;
-use di::{Initialized, WithDependency};
+use super::power_nrf::NrfPowerDriver;
+use super::resources_nrf::NrfCeilingLock;
+use di::resources::Owner;
+use di::singleton::{Singleton, Teardown, TryDefault};
+use di::token::SharedToken;
+use di::InitError;
+use di_macros::{driver, Singleton};
 use nrf52840_hal::clocks::{Clocks, ExternalOscillator};
 use nrf52840_hal::pac::USBD;
 use nrf52840_hal::usbd::{UsbPeripheral, Usbd};
 use static_cell::StaticCell;
+use usb_device::bus::UsbBusAllocator;
+
+pub type NrfUsbBus = Usbd<UsbPeripheral<'static>>;
+
+static HFXO_CLOCKS: StaticCell<Clocks<ExternalOscillator, DontCare, DontCare>> = StaticCell::new();
+static USB_ALLOC: StaticCell<UsbBusAllocator<NrfUsbBus>> = StaticCell::new();
 
 pub struct NrfUsbState {
-    bus: Usbd<UsbPeripheral<'static>>,
+    /// Moves into the bus allocator on first use.
+    usbd: Option<USBD>,
+    alloc: Option<&'static UsbBusAllocator<NrfUsbBus>>,
+    hfxo: SharedToken<'static, NrfHighAccOscToken>,
 }
 
-impl WithDependency<USBD> for NrfUsbState {
-    fn with_dependency<Result, F: FnOnce(USBD) -> Result>(f: F) -> Result {
-        NrfDriverResources::with_ref_mut(|resources| f(resources.usbd.take().unwrap()))
+impl TryDefault for NrfUsbState {
+    fn try_default() -> Result<Self, InitError> {
+        if !NrfPowerDriver.is_vbus_present() {
+            return Err(api::ApiError::UsbdNotPowered.into());
+        }
+
+        let hfxo = NrfHighAccOscillatorDriver::request()?;
+        let usbd = Self::try_with_dependency(|usbd: USBD| usbd)?;
+        Ok(Self {
+            usbd: Some(usbd),
+            alloc: None,
+            hfxo,
+        })
     }
 }
 
-static CLOCKS: StaticCell<Clocks<ExternalOscillator, DontCare, DontCare>> = StaticCell::new();
-
-impl Default for NrfUsbState {
-    fn default() -> Self {
-        NrfHighAccOscillatorDriver.ensure_is_initialized();
-        let token = NrfHighAccOscillatorDriver::request().unwrap();
-        let NrfHighAccOscToken(clocks) = token.acquire();
-        let clocks = CLOCKS.init(clocks);
-        let bus = Self::with_dependency(|usbd| Usbd::new(UsbPeripheral::new(usbd, clocks)));
-        Self { bus }
+impl Teardown for NrfUsbState {
+    /// Only reached before the bus was allocated, the allocated bus keeps
+    /// the driver up.
+    fn teardown(self) {
+        if let Some(usbd) = self.usbd {
+            Self::return_dependency(usbd);
+        }
+        // Releases the HFXO unless another driver still holds it.
+        drop(self.hfxo);
     }
 }
 
+#[derive(Singleton)]
+#[singleton(content = NrfUsbState, lock = NrfCeilingLock<1>)]
 struct NrfUsbDriverState;
 
-impl Singleton for NrfUsbDriverState {
-    type Content = NrfUsbState;
-
-    fn with_state_holder<Result, F>(f: F) -> Result
-    where
-        F: FnOnce(&DriverStateHolder<Self::Content>) -> Result,
-    {
-        static DRIVER_STATE: DriverStateHolder<NrfUsbState> = DriverStateHolder::new();
-        f(&DRIVER_STATE)
-    }
-}
-
-struct NrfUsbDriver;
+#[driver(
+    state = NrfUsbDriverState,
+    depends_on(NrfPowerDriver, NrfHighAccOscillatorDriver)
+)]
+pub struct NrfUsbDriver;
 
 impl NrfUsbDriver {
-    const fn new() -> NrfUsbDriver {
+    pub const fn new() -> NrfUsbDriver {
         NrfUsbDriver
     }
 }
 
-impl Initialized for NrfUsbDriver {
-    fn init(&self) {
-        NrfUsbDriverState.init();
-    }
-
-    fn is_initialized(&self) -> bool {
-        NrfUsbDriverState.is_initialized()
+impl UsbDriver<NrfUsbBus> for NrfUsbDriver {
+    fn usb_alloc(&self) -> &'static UsbBusAllocator<NrfUsbBus> {
+        NrfUsbDriverState::with_ref_mut(|state| {
+            if let Some(alloc) = state.alloc {
+                return alloc;
+            }
+            // The bus borrows the clocks for good, so the share of the HFXO
+            // that witnesses them is never released.
+            let NrfHighAccOscToken(clocks) = state.hfxo.acquire();
+            let clocks = HFXO_CLOCKS.init(clocks);
+            let usbd = state.usbd.take().unwrap();
+            let alloc = USB_ALLOC.init(Usbd::new(UsbPeripheral::new(usbd, clocks)));
+            state.alloc = Some(alloc);
+            // The bus counts as a dependent that never goes away.
+            <Self as Driver>::dependents().acquire();
+            alloc
+        })
     }
 }
-
-impl<'a> UsbDriver<Usbd<UsbPeripheral<'a>>> for NrfUsbDriver {
-    fn bus(&self) -> &'static Usbd<UsbPeripheral<'a>> {
-        NrfUsbDriverState::with_ref(|usb_state| *usb_state.bus)
-    }
-}
-
-impl Driver for NrfUsbDriver {}
@@ -0,0 +1,133 @@
+use super::api::watchdog::*;
+use super::power_nrf::NrfPowerDriver;
+use super::resources_nrf::NrfCeilingLock;
+use core::mem::MaybeUninit;
+use core::ptr;
+use di::resources::Owner;
+use di::singleton::{Singleton, Teardown, TryDefault};
+use di::{InitError, WithDependency};
+use di_macros::{driver, Singleton};
+use nrf52840_hal::pac::WDT;
+
+/// Ticks of the 32.768 kHz clock until a task is considered hung.
+const TIMEOUT_TICKS: u32 = 5 * 32_768;
+
+/// Magic value that reloads a reload register.
+const RELOAD: u32 = 0x6E52_4635;
+
+/// Marks the retained slot as written by the watchdog interrupt.
+const RETAINED_MAGIC: u32 = 0x5744_4F47;
+
+/// Survives the watchdog reset as `.uninit` sections are not zeroed at
+/// startup. Holds the magic and the reload requests that were pending.
+#[link_section = ".uninit.WATCHDOG"]
+static mut RETAINED: MaybeUninit<[u32; 2]> = MaybeUninit::uninit();
+
+/// Returns the pending reload requests recorded before the last reset and
+/// clears the slot.
+fn take_retained() -> u32 {
+    let retained = ptr::addr_of_mut!(RETAINED).cast::<[u32; 2]>();
+    // SAFETY: Only accessed during init and from the watchdog interrupt,
+    // every bit pattern is a valid `u32`.
+    unsafe {
+        let [magic, pending] = ptr::read_volatile(retained);
+        ptr::write_volatile(retained, [0, 0]);
+        if magic == RETAINED_MAGIC {
+            pending
+        } else {
+            0
+        }
+    }
+}
+
+fn store_retained(pending: u32) {
+    let retained = ptr::addr_of_mut!(RETAINED).cast::<[u32; 2]>();
+    // SAFETY: See above.
+    unsafe { ptr::write_volatile(retained, [RETAINED_MAGIC, pending]) }
+}
+
+pub struct NrfWatchdogState {
+    wdt: WDT,
+    last_failure: Option<WatchdogTask>,
+}
+
+impl TryDefault for NrfWatchdogState {
+    fn try_default() -> Result<Self, InitError> {
+        Self::with_dependency(|wdt: WDT| {
+            // The slot is stale unless the watchdog caused the reset.
+            let pending = take_retained();
+            let last_failure = if NrfPowerDriver.take_watchdog_reset() {
+                WatchdogTask::ALL
+                    .into_iter()
+                    .find(|&task| pending & (1 << task as u32) != 0)
+            } else {
+                None
+            };
+            wdt.intenset.write(|w| w.timeout().set());
+
+            Self { wdt, last_failure }
+        })
+    }
+}
+
+impl Teardown for NrfWatchdogState {
+    /// The watchdog cannot be stopped. It resets the chip unless the driver
+    /// is initialized again and the tasks keep checking in.
+    fn teardown(self) {
+        self.wdt.intenclr.write(|w| w.timeout().clear());
+        Self::return_dependency(self.wdt);
+    }
+}
+
+/// Shared with the watchdog interrupt handler which runs at priority 3.
+#[derive(Singleton)]
+#[singleton(content = NrfWatchdogState, lock = NrfCeilingLock<3>)]
+struct NrfWatchdogDriverState;
+
+#[driver(state = NrfWatchdogDriverState, depends_on(NrfPowerDriver))]
+pub struct NrfWatchdogDriver;
+
+impl WatchdogDriver for NrfWatchdogDriver {
+    fn start(&self, tasks: &[WatchdogTask]) {
+        let enabled = tasks
+            .iter()
+            .fold(0, |enabled, &task| enabled | 1 << task as u32);
+        NrfWatchdogDriverState::with_ref(|state| {
+            let wdt = &state.wdt;
+            // The watchdog keeps running across soft resets and cannot be
+            // reconfigured then.
+            if wdt.runstatus.read().bits() != 0 {
+                if wdt.rren.read().bits() != enabled {
+                    defmt::warn!("Watchdog still supervises the tasks from before the reset.");
+                }
+                return;
+            }
+            wdt.config.write(|w| w.sleep().pause().halt().pause());
+            // SAFETY: Any timeout and any set of reload registers is valid.
+            wdt.crv.write(|w| unsafe { w.bits(TIMEOUT_TICKS) });
+            wdt.rren.write(|w| unsafe { w.bits(enabled) });
+            wdt.tasks_start.write(|w| unsafe { w.bits(1) });
+        })
+    }
+
+    /// The watchdog is only reloaded once all tasks have checked in.
+    fn check_in(&self, task: WatchdogTask) {
+        NrfWatchdogDriverState::with_ref(|state| {
+            // SAFETY: Writing the magic value only reloads the register.
+            state.wdt.rr[task as usize].write(|w| unsafe { w.bits(RELOAD) });
+        })
+    }
+
+    fn last_failure(&self) -> Option<WatchdogTask> {
+        NrfWatchdogDriverState::with_ref(|state| state.last_failure)
+    }
+
+    /// Records the tasks that did not check in, the device is reset two
+    /// 32.768 kHz ticks later.
+    fn on_interrupt() {
+        NrfWatchdogDriverState::with_ref(|state| {
+            state.wdt.events_timeout.reset();
+            store_retained(state.wdt.reqstatus.read().bits());
+        })
+    }
+}
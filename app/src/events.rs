@@ -0,0 +1,38 @@
+//! Typed events exchanged between subsystems and application tasks.
+
+use di::bus::Topic;
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+
+#[derive(Clone, Copy, defmt::Format)]
+pub enum NetworkEvent {
+    Configured {
+        address: Ipv4Cidr,
+        router: Option<Ipv4Address>,
+    },
+    Deconfigured,
+}
+
+#[derive(Clone, Copy, defmt::Format)]
+pub enum UsbEvent {
+    Configured,
+    Suspended,
+    Resumed,
+    Reset,
+}
+
+#[derive(Clone, Copy, defmt::Format)]
+pub struct MeasurementEvent {
+    pub co2_ppm: u16,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ButtonEvent {
+    ShortPress,
+    LongPress,
+    DoublePress,
+}
+
+pub static NETWORK: Topic<NetworkEvent, 2, 4> = Topic::new("NETWORK");
+pub static USB: Topic<UsbEvent, 2, 4> = Topic::new("USB");
+pub static MEASUREMENT: Topic<MeasurementEvent, 2, 4> = Topic::new("MEASUREMENT");
+pub static BUTTON: Topic<ButtonEvent, 2, 4> = Topic::new("BUTTON");
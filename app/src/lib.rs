@@ -2,5 +2,7 @@
 #![no_std]
 
 pub mod app;
+pub mod buffers;
 pub mod drivers;
+pub mod events;
 pub mod subsys;
@@ -0,0 +1,85 @@
+//! Debounced push button that turns presses into [`ButtonEvent`]s.
+
+use crate::drivers::api::mono::MonoDriver;
+use crate::events::ButtonEvent;
+use embedded_hal::digital::InputPin;
+use embedded_hal_async::digital::Wait;
+
+pub struct ButtonTimings<Duration> {
+    /// Time the level must be stable after an edge.
+    pub debounce: Duration,
+    /// Presses held at least this long are long presses.
+    pub long_press: Duration,
+    /// Maximum release time between the presses of a double press.
+    pub double_press_gap: Duration,
+}
+
+/// Button that is pulled up and shorts the pin to ground when pressed.
+pub struct Button<'a, P, M: MonoDriver> {
+    pin: P,
+    mono: &'a M,
+    timings: ButtonTimings<M::Duration>,
+}
+
+impl<'a, P, M> Button<'a, P, M>
+where
+    P: InputPin + Wait,
+    M: MonoDriver,
+    M::Duration: Copy,
+{
+    pub fn new(pin: P, mono: &'a M, timings: ButtonTimings<M::Duration>) -> Self {
+        Self { pin, mono, timings }
+    }
+
+    /// Waits for the next complete press. Short presses are only reported
+    /// once the double press gap has passed.
+    pub async fn next_event(&mut self) -> ButtonEvent {
+        let mono = self.mono;
+        let long_press = self.timings.long_press;
+        let double_press_gap = self.timings.double_press_gap;
+
+        self.pressed().await;
+        if mono
+            .timeout_after(long_press, self.released())
+            .await
+            .is_err()
+        {
+            self.released().await;
+            return ButtonEvent::LongPress;
+        }
+
+        match mono.timeout_after(double_press_gap, self.pressed()).await {
+            Ok(()) => {
+                self.released().await;
+                ButtonEvent::DoublePress
+            }
+            Err(_) => ButtonEvent::ShortPress,
+        }
+    }
+
+    async fn pressed(&mut self) {
+        self.settle(false).await
+    }
+
+    async fn released(&mut self) {
+        self.settle(true).await
+    }
+
+    /// Waits until the pin is at the given level for the debounce time.
+    async fn settle(&mut self, high: bool) {
+        loop {
+            let reached = if high {
+                self.pin.wait_for_high().await
+            } else {
+                self.pin.wait_for_low().await
+            };
+            if reached.is_err() {
+                continue;
+            }
+            self.mono.delay(self.timings.debounce).await;
+            if self.pin.is_high().is_ok_and(|level| level == high) {
+                return;
+            }
+        }
+    }
+}
@@ -1,35 +1,32 @@
-use super::{HalUsbBus, SubsysUsbClass, SubsysUsbClassFactory};
+use super::{SubsysUsbClass, SubsysUsbClassFactory};
+use crate::buffers;
 use crate::drivers::api::usb::UsbDriver;
 use defmt;
-use static_cell::{ConstStaticCell, StaticCell};
 use usb_device::class_prelude::*;
 use usbd_ethernet::{DeviceState, Ethernet};
 
 const HOST_MAC_ADDR: [u8; 6] = [0x1e, 0x30, 0x6c, 0xa2, 0xc1, 0x66];
 
-static ETHERNET_IN_BUFFER: ConstStaticCell<[u8; 2048]> = ConstStaticCell::new([0; 2048]);
-static ETHERNET_OUT_BUFFER: ConstStaticCell<[u8; 2048]> = ConstStaticCell::new([0; 2048]);
-
-static CDC_NCM_ETH_CLASS: StaticCell<CdcNcmEthClass> = StaticCell::new();
-
-pub struct CdcNcmEthClass {
-    pub usb_class: Ethernet<'static, HalUsbBus<'static>>,
+pub struct CdcNcmEthClass<B: UsbBus + 'static> {
+    pub usb_class: Ethernet<'static, B>,
 }
 
-impl SubsysUsbClassFactory<HalUsbBus<'static>> for CdcNcmEthClass {
-    fn new<'a>(usb_driver: &'static dyn UsbDriver<HalUsbBus<'static>>) -> &'a mut Self {
+impl<B: UsbBus> SubsysUsbClassFactory<B> for CdcNcmEthClass<B> {
+    fn new<U: UsbDriver<B>>(usb_driver: &'static U) -> Self {
+        let in_buffer = buffers::ETHERNET.alloc_zeroed().unwrap().leak();
+        let out_buffer = buffers::ETHERNET.alloc_zeroed().unwrap().leak();
         let usb_class = Ethernet::new(
             usb_driver.usb_alloc(),
             HOST_MAC_ADDR,
             64,
-            ETHERNET_IN_BUFFER.take(),
-            ETHERNET_OUT_BUFFER.take(),
+            in_buffer,
+            out_buffer,
         );
-        CDC_NCM_ETH_CLASS.init(CdcNcmEthClass { usb_class })
+        CdcNcmEthClass { usb_class }
     }
 }
 
-impl SubsysUsbClass<HalUsbBus<'static>> for CdcNcmEthClass {
+impl<B: UsbBus> SubsysUsbClass<B> for CdcNcmEthClass<B> {
     fn handle_signal(&mut self) {
         if self.usb_class.state() == DeviceState::Disconnected {
             if self.usb_class.connection_speed().is_none() {
@@ -47,7 +44,7 @@ impl SubsysUsbClass<HalUsbBus<'static>> for CdcNcmEthClass {
         }
     }
 
-    fn usb_class(&mut self) -> &mut dyn UsbClass<HalUsbBus<'static>> {
+    fn usb_class(&mut self) -> &mut dyn UsbClass<B> {
         &mut self.usb_class
     }
 }
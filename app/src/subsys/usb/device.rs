@@ -0,0 +1,69 @@
+use super::{SubsysUsbClass, SubsysUsbDevice, SubsysUsbDeviceFactory};
+use crate::drivers::api::usb::UsbDriver;
+use crate::events::{self, UsbEvent};
+use usb_device::class_prelude::UsbBus;
+use usb_device::device::*;
+
+const MANUFACTURER: &str = "CfH";
+const PRODUCT: &str = "CO2 Gateway";
+const SERIAL_NUMBER: &str = "00001";
+const VENDOR_ID: u16 = 0x1209;
+const PRODUCT_ID: u16 = 0x0004;
+
+/// USB device exposing a single class. Devices are plain values, so the
+/// application can create one per bus and poll it from wherever it owns it.
+pub struct HalUsbDevice<B: UsbBus + 'static, C: 'static> {
+    usb_dev: UsbDevice<'static, B>,
+    class: &'static mut C,
+    state: UsbDeviceState,
+}
+
+impl<B: UsbBus, C> HalUsbDevice<B, C> {
+    /// Publishes state transitions of the device.
+    fn update_state(&mut self) {
+        let state = self.usb_dev.state();
+        if state == self.state {
+            return;
+        }
+        let event = match (self.state, state) {
+            (_, UsbDeviceState::Suspend) => Some(UsbEvent::Suspended),
+            (UsbDeviceState::Suspend, _) => Some(UsbEvent::Resumed),
+            (_, UsbDeviceState::Configured) => Some(UsbEvent::Configured),
+            (_, UsbDeviceState::Default) => Some(UsbEvent::Reset),
+            (_, UsbDeviceState::Addressed) => None,
+        };
+        self.state = state;
+        if let Some(event) = event {
+            events::USB.publish(event);
+        }
+    }
+}
+
+impl<B: UsbBus, C: SubsysUsbClass<B>> SubsysUsbDeviceFactory<B, C> for HalUsbDevice<B, C> {
+    fn new<U: UsbDriver<B>>(usb_driver: &'static U, class: &'static mut C) -> Self {
+        let usb_dev =
+            UsbDeviceBuilder::new(usb_driver.usb_alloc(), UsbVidPid(VENDOR_ID, PRODUCT_ID))
+                .device_class(usbd_ethernet::USB_CLASS_CDC)
+                .strings(&[StringDescriptors::default()
+                    .manufacturer(MANUFACTURER)
+                    .product(PRODUCT)
+                    .serial_number(SERIAL_NUMBER)])
+                .unwrap()
+                .build();
+        HalUsbDevice {
+            usb_dev,
+            class,
+            state: UsbDeviceState::Default,
+        }
+    }
+}
+
+impl<B: UsbBus, C: SubsysUsbClass<B>> SubsysUsbDevice<B, C> for HalUsbDevice<B, C> {
+    fn poll(&mut self, f: &mut dyn FnMut(&mut C)) {
+        if self.usb_dev.poll(&mut [self.class.usb_class()]) {
+            self.class.handle_signal();
+            f(self.class);
+        }
+        self.update_state();
+    }
+}
@@ -0,0 +1,2 @@
+/* Nothing but the firmware is flashed, storage takes the last pages. */
+__storage_end = ORIGIN(FLASH) + LENGTH(FLASH);
@@ -0,0 +1,3 @@
+/* The USB bootloader and its settings occupy FLASH from 0xE0000 up,
+   storage ends right below. */
+__storage_end = 0xE0000;
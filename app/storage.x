@@ -0,0 +1,11 @@
+/* Reserves pages of FLASH for persistent storage. The board's
+   storage-*.x defines where the region ends. */
+INCLUDE storage-board.x
+__storage_size = 16K;
+__storage_start = __storage_end - __storage_size;
+
+ASSERT(__storage_start % 4K == 0, "storage must start at a flash page");
+ASSERT(__storage_end <= ORIGIN(FLASH) + LENGTH(FLASH),
+       "storage must end within FLASH");
+ASSERT(LOADADDR(.data) + SIZEOF(.data) <= __storage_start,
+       "firmware overlaps the storage region, reduce __storage_size");
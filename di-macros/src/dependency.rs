@@ -0,0 +1,108 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{spanned::Spanned, Data, DeriveInput, Error, Fields, Path, Result};
+
+const MAX_PARTS: usize = 4;
+
+struct DependencyArgs {
+    owner: Path,
+}
+
+impl DependencyArgs {
+    fn parse(input: &DeriveInput) -> Result<Self> {
+        let mut owner = None;
+        for attr in input
+            .attrs
+            .iter()
+            .filter(|a| a.path().is_ident("dependency"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("owner") {
+                    owner = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported dependency argument"))
+                }
+            })?;
+        }
+        let owner = owner.ok_or_else(|| {
+            Error::new(
+                input.ident.span(),
+                "missing `#[dependency(owner = ...)]` attribute",
+            )
+        })?;
+        Ok(Self { owner })
+    }
+}
+
+pub fn derive(input: DeriveInput) -> Result<TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new(
+                    input.span(),
+                    "dependencies must be structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.span(),
+                "dependencies must be structs with named fields",
+            ))
+        }
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(
+            input.generics.span(),
+            "dependencies cannot be generic",
+        ));
+    }
+    if fields.is_empty() || fields.len() > MAX_PARTS {
+        return Err(Error::new(
+            input.span(),
+            format!("dependencies must have between 1 and {MAX_PARTS} parts"),
+        ));
+    }
+
+    let DependencyArgs { owner } = DependencyArgs::parse(&input)?;
+    let ident = &input.ident;
+    let names: Vec<_> = fields.iter().map(|field| &field.ident).collect();
+    let tys = fields.iter().map(|field| &field.ty);
+
+    // A single part is claimed as is, several parts as a tuple.
+    let (parts_ty, parts) = if fields.len() == 1 {
+        (quote! { #(#tys)* }, quote! { #(#names)* })
+    } else {
+        (quote! { (#(#tys),*) }, quote! { (#(#names),*) })
+    };
+
+    Ok(quote! {
+        impl ::di::resources::Composite for #ident {
+            type Parts = #parts_ty;
+
+            fn from_parts(#parts: Self::Parts) -> Self {
+                Self { #(#names),* }
+            }
+
+            fn into_parts(self) -> Self::Parts {
+                let Self { #(#names),* } = self;
+                #parts
+            }
+        }
+
+        impl ::di::WithDependency<#ident> for #owner {
+            fn with_dependency<__Result, __F>(
+                f: __F,
+            ) -> ::core::result::Result<__Result, ::di::InitError>
+            where
+                __F: FnOnce(#ident) -> __Result,
+            {
+                <Self as ::di::WithDependency<#parts_ty>>::with_dependency(|parts| {
+                    f(::di::resources::Composite::from_parts(parts))
+                })
+            }
+        }
+    })
+}
@@ -0,0 +1,242 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    meta::ParseNestedMeta, parse_quote, punctuated::Punctuated, spanned::Spanned, Error, Expr,
+    ExprPath, Fields, ItemStruct, Path, Result, Token,
+};
+
+#[derive(Default)]
+pub struct DriverArgs {
+    state: Option<Path>,
+    init: Option<ExprPath>,
+    deinit: Option<ExprPath>,
+    depends_on: Vec<Path>,
+    after: Vec<Path>,
+}
+
+impl DriverArgs {
+    pub fn parse(&mut self, meta: ParseNestedMeta) -> Result<()> {
+        if meta.path.is_ident("state") {
+            self.state = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("init") {
+            self.init = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("deinit") {
+            self.deinit = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("depends_on") {
+            let content;
+            syn::parenthesized!(content in meta.input);
+            let deps = Punctuated::<Path, Token![,]>::parse_terminated(&content)?;
+            self.depends_on.extend(deps);
+            Ok(())
+        } else if meta.path.is_ident("after") {
+            let content;
+            syn::parenthesized!(content in meta.input);
+            let drivers = Punctuated::<Path, Token![,]>::parse_terminated(&content)?;
+            self.after.extend(drivers);
+            Ok(())
+        } else {
+            Err(meta.error("unsupported driver argument"))
+        }
+    }
+}
+
+pub fn expand(args: DriverArgs, item: ItemStruct) -> Result<TokenStream> {
+    if !matches!(item.fields, Fields::Unit) {
+        return Err(Error::new(
+            item.span(),
+            "drivers must be declared as unit structs",
+        ));
+    }
+    if !item.generics.params.is_empty() {
+        return Err(Error::new(
+            item.generics.span(),
+            "drivers cannot be generic",
+        ));
+    }
+
+    let DriverArgs {
+        state,
+        init,
+        deinit,
+        depends_on,
+        after,
+    } = args;
+    let ident = &item.ident;
+
+    // Hooks return a `Result` whose error converts into `InitError` and
+    // `DeinitError` respectively.
+    let init_call: Option<Expr> = init.map(|init| parse_quote! { #init(self) });
+    let deinit_call: Option<Expr> = deinit.map(|deinit| parse_quote! { #deinit(self) });
+    let init_hook = init_call.as_ref().map(|call| {
+        quote! { #call.map_err(::core::convert::Into::<::di::InitError>::into)?; }
+    });
+    // A failing deinit hook leaves the driver up.
+    let deinit_hook = deinit_call.as_ref().map(|call| {
+        quote! { #call.map_err(::core::convert::Into::<::di::DeinitError>::into)?; }
+    });
+    let teardown: Option<Expr> = state.as_ref().map(|state| {
+        parse_quote! {
+            <#state as ::di::singleton::Singleton>::deinit_with(
+                ::di::singleton::Teardown::teardown,
+            )
+        }
+    });
+    // The state has to exist for the hook to use it, so it is torn down
+    // again if the hook fails. The deinit hook is not run as the driver never
+    // came up, and the dependencies were not acquired yet. An async task may
+    // have locked the fresh state meanwhile, it then stays up.
+    let init_hook_or_teardown = init_call.map(|call| {
+        quote! {
+            if let ::core::result::Result::Err(err) = #call {
+                let _ = #teardown;
+                return ::core::result::Result::Err(::core::convert::Into::<::di::InitError>::into(err));
+            }
+        }
+    });
+    // The init order is resolved up front from the dependency graph, so
+    // dependencies are checked here rather than initialized implicitly.
+    let init_deps = depends_on.iter().map(|dep| {
+        quote! {
+            if !::di::TryInitialized::is_initialized(&#dep) {
+                return ::core::result::Result::Err(::di::InitError::Dependency {
+                    driver: stringify!(#ident),
+                    dependency: stringify!(#dep),
+                });
+            }
+        }
+    });
+    let init_deps = quote! {
+        if ::di::TryInitialized::is_initialized(self) {
+            return ::core::result::Result::Err(::di::InitError::AlreadyInitialized);
+        }
+        #(#init_deps)*
+    };
+    let acquire_deps = quote! {
+        #(<#depends_on as ::di::driver::Driver>::dependents().acquire();)*
+    };
+    let release_deps = quote! {
+        #(<#depends_on as ::di::driver::Driver>::dependents().release();)*
+    };
+    // Dependents keep using the driver, so it stays up until they are gone.
+    let check_dependents = quote! {
+        if !::di::TryInitialized::is_initialized(self) {
+            return ::core::result::Result::Ok(());
+        }
+        let dependents = <Self as ::di::driver::Driver>::dependents().count();
+        if dependents > 0 {
+            return ::core::result::Result::Err(::di::DeinitError::InUse {
+                driver: stringify!(#ident),
+                dependents,
+            });
+        }
+    };
+
+    let state_descriptor = match &state {
+        Some(state) => quote! {
+            ::core::option::Option::Some(&<#state as ::di::singleton::Singleton>::DESCRIPTOR)
+        },
+        None => quote! { ::core::option::Option::None },
+    };
+
+    let impls = match state {
+        Some(state) => quote! {
+            impl ::di::TryInitialized for #ident {
+                fn try_init(&self) -> ::core::result::Result<(), ::di::InitError> {
+                    #init_deps
+                    <#state as ::di::singleton::Singleton>::state_holder()
+                        .try_init_with(<#state as ::di::singleton::Singleton>::try_new)?;
+                    <#state as ::di::singleton::Singleton>::register();
+                    #init_hook_or_teardown
+                    #acquire_deps
+                    ::core::result::Result::Ok(())
+                }
+
+                fn is_initialized(&self) -> bool {
+                    <#state as ::di::singleton::Singleton>::state_holder().is_initialized()
+                }
+            }
+
+            impl ::di::Deinitialized for #ident {
+                fn deinit(&self) -> ::core::result::Result<(), ::di::DeinitError> {
+                    #check_dependents
+                    let locked = ::di::DeinitError::Locked {
+                        driver: stringify!(#ident),
+                    };
+                    if <#state as ::di::singleton::Singleton>::state_holder().is_locked() {
+                        return ::core::result::Result::Err(locked);
+                    }
+                    #deinit_hook
+                    #teardown.map_err(|_| locked)?;
+                    #release_deps
+                    ::core::result::Result::Ok(())
+                }
+            }
+        },
+        None => quote! {
+            impl ::di::driver::StatelessDriver for #ident {
+                fn init_state() -> &'static ::di::driver::InitState {
+                    static INIT_STATE: ::di::driver::InitState = ::di::driver::InitState::new();
+                    &INIT_STATE
+                }
+            }
+
+            impl ::di::TryInitialized for #ident {
+                fn try_init(&self) -> ::core::result::Result<(), ::di::InitError> {
+                    #init_deps
+                    #init_hook
+                    ::di::Initialized::init(<Self as ::di::driver::StatelessDriver>::init_state());
+                    #acquire_deps
+                    ::core::result::Result::Ok(())
+                }
+
+                fn is_initialized(&self) -> bool {
+                    ::di::Initialized::is_initialized(
+                        <Self as ::di::driver::StatelessDriver>::init_state(),
+                    )
+                }
+            }
+
+            impl ::di::Deinitialized for #ident {
+                fn deinit(&self) -> ::core::result::Result<(), ::di::DeinitError> {
+                    #check_dependents
+                    #deinit_hook
+                    <Self as ::di::driver::StatelessDriver>::init_state().reset();
+                    #release_deps
+                    ::core::result::Result::Ok(())
+                }
+            }
+        },
+    };
+
+    Ok(quote! {
+        #item
+
+        #impls
+
+        impl ::di::driver::Driver for #ident {
+            const DESCRIPTOR: ::di::driver::DriverDescriptor =
+                ::di::driver::DriverDescriptor::new(
+                    {
+                        static ID: ::di::driver::DriverId = ::di::driver::DriverId::new(
+                            concat!(module_path!(), "::", stringify!(#ident)),
+                        );
+                        &ID
+                    },
+                    &[#(&<#depends_on as ::di::driver::Driver>::DESCRIPTOR),*],
+                    &[#(&<#after as ::di::driver::Driver>::DESCRIPTOR),*],
+                    #state_descriptor,
+                    || ::di::TryInitialized::try_init(&#ident),
+                    || ::di::Deinitialized::deinit(&#ident),
+                    || ::di::TryInitialized::is_initialized(&#ident),
+                );
+
+            fn dependents() -> &'static ::di::driver::Dependents {
+                static DEPENDENTS: ::di::driver::Dependents = ::di::driver::Dependents::new();
+                &DEPENDENTS
+            }
+        }
+    })
+}
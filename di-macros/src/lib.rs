@@ -1,2 +1,109 @@
-#![no_std]
-#![no_main]
+mod dependency;
+mod driver;
+mod resources;
+mod singleton;
+
+use proc_macro::TokenStream;
+use syn::{meta, parse_macro_input, DeriveInput, ItemStruct};
+
+/// Implements `di::singleton::Singleton` for a unit struct together with the
+/// static state holder backing it.
+///
+/// The content is guarded by a global critical section unless another
+/// `di::lock::Lock` is chosen with `lock = ...`. With `instances = N` the
+/// struct implements `di::singleton::Instances` instead and each instance is
+/// accessed as `di::singleton::Instance<Struct, INDEX>`. The content is
+/// created with `di::singleton::TryDefault` unless `new = ...` names a
+/// function that creates the content of an instance from its index.
+///
+/// ```ignore
+/// #[derive(Singleton)]
+/// #[singleton(content = NrfGpioState, lock = NrfCeilingLock<1>)]
+/// struct NrfGpioDriverState;
+/// ```
+#[proc_macro_derive(Singleton, attributes(singleton))]
+pub fn derive_singleton(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    singleton::derive(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `di::resources::Resources` for a struct holding peripherals.
+///
+/// Every `Option<_>` field must name its unique owner with `#[claimed_by(...)]`.
+/// The owner can then take the peripheral through `di::WithDependency`.
+/// Unclaimed, doubly claimed or duplicate peripherals fail to compile.
+///
+/// ```ignore
+/// #[derive(Resources)]
+/// #[resources(singleton = NrfDriverResources)]
+/// struct NrfResources {
+///     #[claimed_by(NrfPowerDriver)]
+///     power: Option<POWER>,
+/// }
+/// ```
+#[proc_macro_derive(Resources, attributes(resources, claimed_by))]
+pub fn derive_resources(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    resources::derive(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `di::resources::Composite` for a struct of peripherals and
+/// lets its owner take them through `di::WithDependency` in one go.
+///
+/// All parts must be claimed by the same owner. They are claimed atomically,
+/// i.e. nothing is claimed if any part has already been taken.
+///
+/// ```ignore
+/// #[derive(Dependency)]
+/// #[dependency(owner = NrfTwimState)]
+/// struct NrfTwimPeripherals {
+///     twim: TWIM0,
+///     p0: P0,
+/// }
+/// ```
+#[proc_macro_derive(Dependency, attributes(dependency))]
+pub fn derive_dependency(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    dependency::derive(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `di::TryInitialized` and `di::driver::Driver` for a driver
+/// struct.
+///
+/// Stateless drivers get their own static init state. Drivers backed by a
+/// singleton forward to it with `state = ...`. An optional `init = ...` hook
+/// is called with `&self` once the state exists and returns a `Result` whose
+/// error converts into `di::InitError`. The state is torn down again if the
+/// hook fails. On deinit the state is handed to its
+/// `di::singleton::Teardown` impl, which returns claimed resources. An
+/// optional `deinit = ...` hook is called with `&self` before that and
+/// returns a `Result` whose error converts into `di::DeinitError`. The driver
+/// stays up if the hook fails.
+/// `depends_on(...)` lists drivers that must be initialized first. A driver
+/// refuses to be deinitialized while drivers depending on it are
+/// initialized. `after(...)` only orders the driver after others, it is
+/// initialized even if they fail.
+///
+/// ```ignore
+/// #[driver(init = Self::start, depends_on(NrfSleepOscillatorDriver))]
+/// struct NrfRticMonoDriver;
+///
+/// #[driver(state = NrfGpioDriverState)]
+/// struct NrfGpioDriver;
+/// ```
+#[proc_macro_attribute]
+pub fn driver(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut driver_args = driver::DriverArgs::default();
+    let parser = meta::parser(|meta| driver_args.parse(meta));
+    parse_macro_input!(args with parser);
+    let item = parse_macro_input!(item as ItemStruct);
+    driver::expand(driver_args, item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
@@ -0,0 +1,159 @@
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned, ToTokens};
+use syn::{
+    spanned::Spanned, Data, DeriveInput, Error, Fields, GenericArgument, Path, PathArguments,
+    Result, Type,
+};
+
+struct ResourcesArgs {
+    singleton: Path,
+}
+
+impl ResourcesArgs {
+    fn parse(input: &DeriveInput) -> Result<Self> {
+        let mut singleton = None;
+        for attr in input
+            .attrs
+            .iter()
+            .filter(|a| a.path().is_ident("resources"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("singleton") {
+                    singleton = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported resources argument"))
+                }
+            })?;
+        }
+        let singleton = singleton.ok_or_else(|| {
+            Error::new(
+                input.ident.span(),
+                "missing `#[resources(singleton = ...)]` attribute",
+            )
+        })?;
+        Ok(Self { singleton })
+    }
+}
+
+struct Peripheral<'a> {
+    field: &'a syn::Ident,
+    ty: &'a Type,
+    owner: Path,
+}
+
+/// Extracts `T` from a field of type `Option<T>`.
+fn optional_type(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+pub fn derive(input: DeriveInput) -> Result<TokenStream> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new(input.span(), "resources must be a struct"));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new(
+            input.span(),
+            "resources must be a struct with named fields",
+        ));
+    };
+    let ResourcesArgs { singleton } = ResourcesArgs::parse(&input)?;
+    let ident = &input.ident;
+
+    let mut peripherals: Vec<Peripheral> = Vec::new();
+    for field in &fields.named {
+        let name = field.ident.as_ref().expect("named field");
+        let ty = optional_type(&field.ty).ok_or_else(|| {
+            Error::new(field.ty.span(), "peripherals must be stored as `Option<_>`")
+        })?;
+
+        let mut owner: Option<Path> = None;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|a| a.path().is_ident("claimed_by"))
+        {
+            if owner.is_some() {
+                return Err(Error::new(
+                    attr.span(),
+                    format!("peripheral `{name}` is claimed by more than one driver"),
+                ));
+            }
+            owner = Some(attr.parse_args()?);
+        }
+        let owner = owner.ok_or_else(|| {
+            Error::new(
+                name.span(),
+                format!("peripheral `{name}` is not claimed by any driver"),
+            )
+        })?;
+
+        peripherals.push(Peripheral {
+            field: name,
+            ty,
+            owner,
+        });
+    }
+
+    // Two fields of the same type make the `Claim` impls conflict. Unlike
+    // comparing the tokens this also catches the same type under different
+    // paths or aliases.
+    let impls = peripherals.iter().map(|Peripheral { field, ty, owner }| {
+        let already_claimed = format!("{} already claimed", ty.to_token_stream());
+        let not_claimed = format!("{} returned but not claimed", ty.to_token_stream());
+        quote_spanned! {ty.span()=>
+            impl ::di::resources::Claim<#ty> for #ident {
+                fn check(&self) -> ::core::result::Result<(), ::di::resources::ClaimError> {
+                    if self.#field.is_some() {
+                        Ok(())
+                    } else {
+                        Err(::di::resources::ClaimError(#already_claimed))
+                    }
+                }
+
+                fn claim(&mut self) -> #ty {
+                    self.#field.take().expect(#already_claimed)
+                }
+
+                fn restore(&mut self, dependency: #ty) {
+                    assert!(self.#field.replace(dependency).is_none(), #not_claimed);
+                }
+            }
+
+            impl ::di::resources::Owner<#ty> for #owner {
+                type Resources = #singleton;
+            }
+        }
+    });
+    let ownership = peripherals.iter().map(|Peripheral { ty, owner, .. }| {
+        let peripheral = ty.to_token_stream().to_string();
+        let owner = owner.to_token_stream().to_string();
+        quote! {
+            ::di::resources::Ownership {
+                peripheral: #peripheral,
+                owner: #owner,
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl ::di::resources::Resources for #ident {
+            const OWNERSHIP: &'static [::di::resources::Ownership] = &[#(#ownership),*];
+        }
+
+        #(#impls)*
+    })
+}
@@ -0,0 +1,149 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{spanned::Spanned, Data, DeriveInput, Error, Expr, ExprPath, Fields, Result, Type};
+
+struct SingletonArgs {
+    content: Type,
+    lock: Option<Type>,
+    instances: Option<Expr>,
+    new: Option<ExprPath>,
+}
+
+impl SingletonArgs {
+    fn parse(input: &DeriveInput) -> Result<Self> {
+        let mut content = None;
+        let mut lock = None;
+        let mut instances = None;
+        let mut new: Option<ExprPath> = None;
+        for attr in input
+            .attrs
+            .iter()
+            .filter(|a| a.path().is_ident("singleton"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("content") {
+                    content = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("lock") {
+                    lock = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("instances") {
+                    instances = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("new") {
+                    new = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported singleton argument"))
+                }
+            })?;
+        }
+        let content = content.ok_or_else(|| {
+            Error::new(
+                input.ident.span(),
+                "missing `#[singleton(content = ...)]` attribute",
+            )
+        })?;
+        if let (Some(new), None) = (&new, &instances) {
+            return Err(Error::new(
+                new.span(),
+                "`new = ...` requires `instances = ...`",
+            ));
+        }
+        Ok(Self {
+            content,
+            lock,
+            instances,
+            new,
+        })
+    }
+}
+
+pub fn derive(input: DeriveInput) -> Result<TokenStream> {
+    match &input.data {
+        Data::Struct(data) if matches!(data.fields, Fields::Unit) => {}
+        _ => {
+            return Err(Error::new(
+                input.span(),
+                "singletons must be declared as unit structs",
+            ))
+        }
+    }
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(
+            input.generics.span(),
+            "singletons cannot be generic",
+        ));
+    }
+
+    let SingletonArgs {
+        content,
+        lock,
+        instances,
+        new,
+    } = SingletonArgs::parse(&input)?;
+    let ident = &input.ident;
+    let lock = lock.unwrap_or_else(|| syn::parse_quote!(::di::lock::CriticalSectionLock));
+
+    let try_default = quote! {
+        <#content as ::di::singleton::TryDefault>::try_default()
+    };
+
+    if let Some(count) = instances {
+        let try_new = match new {
+            Some(new) => quote! {
+                #new(index).map_err(::core::convert::Into::<::di::InitError>::into)
+            },
+            None => quote! {
+                let _ = index;
+                #try_default
+            },
+        };
+        return Ok(quote! {
+            impl ::di::singleton::Instances for #ident {
+                type Content = #content;
+                type Lock = #lock;
+
+                const NAME: &'static str = concat!(module_path!(), "::", stringify!(#ident));
+                const COUNT: usize = #count;
+
+                fn state_holders(
+                ) -> &'static [::di::singleton::SingletonHolderImpl<Self::Content, Self::Lock>] {
+                    static STATE_HOLDERS: [::di::singleton::SingletonHolderImpl<#content, #lock>;
+                        #count] = [const { ::di::singleton::SingletonHolderImpl::new() }; #count];
+                    &STATE_HOLDERS
+                }
+
+                fn try_new(
+                    index: usize,
+                ) -> ::core::result::Result<Self::Content, ::di::InitError> {
+                    #try_new
+                }
+            }
+        });
+    }
+
+    Ok(quote! {
+        impl ::di::singleton::Singleton for #ident {
+            type Content = #content;
+            type Lock = #lock;
+
+            const DESCRIPTOR: ::di::registry::SingletonDescriptor =
+                ::di::registry::SingletonDescriptor::new(
+                    concat!(module_path!(), "::", stringify!(#ident)),
+                    || ::di::TryInitialized::is_initialized(&#ident),
+                );
+
+            fn state_holder(
+            ) -> &'static ::di::singleton::SingletonHolderImpl<Self::Content, Self::Lock> {
+                static STATE_HOLDER: ::di::singleton::SingletonHolderImpl<#content, #lock> =
+                    ::di::singleton::SingletonHolderImpl::new();
+                &STATE_HOLDER
+            }
+
+            fn try_new() -> ::core::result::Result<Self::Content, ::di::InitError> {
+                #try_default
+            }
+        }
+    })
+}
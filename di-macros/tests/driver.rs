@@ -0,0 +1,83 @@
+use di::singleton::{Singleton, Teardown};
+use di::{DeinitError, Deinitialized, TryInitialized};
+use di_macros::{driver, Singleton};
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[derive(Debug)]
+struct Busy;
+
+impl fmt::Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "busy")
+    }
+}
+
+impl Error for Busy {}
+
+static BUSY: Busy = Busy;
+
+impl From<Busy> for DeinitError {
+    fn from(_: Busy) -> Self {
+        DeinitError::Driver(&BUSY)
+    }
+}
+
+static TEARDOWNS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Default)]
+struct State;
+
+impl Teardown for State {
+    fn teardown(self) {
+        TEARDOWNS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Singleton)]
+#[singleton(content = State)]
+struct StateHolder;
+
+static STOP_FAILS: AtomicBool = AtomicBool::new(true);
+
+#[driver(state = StateHolder, deinit = Self::stop)]
+struct Stateful;
+
+impl Stateful {
+    fn stop(&self) -> Result<(), Busy> {
+        StateHolder::with_ref(|_| ());
+        if STOP_FAILS.load(Ordering::Relaxed) {
+            Err(Busy)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[driver(deinit = Self::stop)]
+struct Stateless;
+
+impl Stateless {
+    fn stop(&self) -> Result<(), Busy> {
+        Err(Busy)
+    }
+}
+
+#[test]
+fn test_failing_deinit_hook() {
+    Stateful.try_init().unwrap();
+    let err = Stateful.deinit().unwrap_err();
+    assert!(err.driver_error::<Busy>().is_some());
+    assert!(Stateful.is_initialized());
+    assert_eq!(TEARDOWNS.load(Ordering::Relaxed), 0);
+
+    STOP_FAILS.store(false, Ordering::Relaxed);
+    Stateful.deinit().unwrap();
+    assert!(!Stateful.is_initialized());
+    assert_eq!(TEARDOWNS.load(Ordering::Relaxed), 1);
+
+    Stateless.try_init().unwrap();
+    assert_eq!(Stateless.deinit(), Err(DeinitError::Driver(&BUSY)));
+    assert!(Stateless.is_initialized());
+}
@@ -0,0 +1,40 @@
+use di::resources::ClaimError;
+use di::{InitError, TryInitialized, WithDependency};
+use di_macros::{Resources, Singleton};
+
+struct Nvmc;
+
+struct FlashState;
+
+#[derive(Resources)]
+#[resources(singleton = DriverResources)]
+struct TestResources {
+    #[claimed_by(FlashState)]
+    nvmc: Option<Nvmc>,
+}
+
+impl Default for TestResources {
+    fn default() -> Self {
+        Self { nvmc: Some(Nvmc) }
+    }
+}
+
+#[derive(Singleton)]
+#[singleton(content = TestResources)]
+struct DriverResources;
+
+#[test]
+fn test_claim_twice() {
+    DriverResources.try_init().unwrap();
+    assert_eq!(FlashState::with_dependency(|_: Nvmc| ()), Ok(()));
+    assert_eq!(
+        FlashState::with_dependency(|_: Nvmc| ()),
+        Err(InitError::Claim(ClaimError("Nvmc already claimed")))
+    );
+}
+
+#[test]
+fn test_ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
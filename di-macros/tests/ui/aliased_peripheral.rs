@@ -0,0 +1,24 @@
+use di_macros::{Resources, Singleton};
+
+mod pac {
+    pub struct NVMC;
+}
+
+use pac::NVMC;
+
+pub struct FlashState;
+
+#[derive(Resources, Default)]
+#[resources(singleton = DriverResources)]
+struct TestResources {
+    #[claimed_by(FlashState)]
+    nvmc: Option<NVMC>,
+    #[claimed_by(FlashState)]
+    flash: Option<pac::NVMC>,
+}
+
+#[derive(Singleton)]
+#[singleton(content = TestResources)]
+struct DriverResources;
+
+fn main() {}
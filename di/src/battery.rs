@@ -0,0 +1,69 @@
+/// Discharge curve of a single LiPo cell, in descending voltage.
+pub const LIPO_CURVE: &[(u32, u8)] = &[
+    (4200, 100),
+    (4100, 90),
+    (4000, 80),
+    (3900, 60),
+    (3800, 40),
+    (3700, 20),
+    (3600, 10),
+    (3500, 5),
+    (3300, 0),
+];
+
+/// Interpolates the charge in percent on a discharge curve given as
+/// `(millivolts, percent)` points in descending voltage. Voltages outside the
+/// curve are clamped to its ends.
+pub fn battery_percent(millivolts: u32, curve: &[(u32, u8)]) -> u8 {
+    let Some(&(max_mv, max_percent)) = curve.first() else {
+        return 0;
+    };
+    if millivolts >= max_mv {
+        return max_percent;
+    }
+    for points in curve.windows(2) {
+        let [(high_mv, high_percent), (low_mv, low_percent)] = [points[0], points[1]];
+        if high_mv <= low_mv || millivolts < low_mv || millivolts > high_mv {
+            continue;
+        }
+        let span = i64::from(high_percent) - i64::from(low_percent);
+        let offset = span * i64::from(millivolts - low_mv) / i64::from(high_mv - low_mv);
+        // Lies between the percentages of the two points.
+        return (i64::from(low_percent) + offset) as u8;
+    }
+    curve.last().map_or(0, |&(_, percent)| percent)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_endpoints() {
+        assert_eq!(battery_percent(4200, LIPO_CURVE), 100);
+        assert_eq!(battery_percent(3300, LIPO_CURVE), 0);
+        for &(millivolts, percent) in LIPO_CURVE {
+            assert_eq!(battery_percent(millivolts, LIPO_CURVE), percent);
+        }
+    }
+
+    #[test]
+    fn test_interpolation() {
+        assert_eq!(battery_percent(4150, LIPO_CURVE), 95);
+        assert_eq!(battery_percent(3950, LIPO_CURVE), 70);
+        assert_eq!(battery_percent(3850, LIPO_CURVE), 50);
+        assert_eq!(battery_percent(3650, LIPO_CURVE), 15);
+        // Rounds down between 3500 mV (5 %) and 3300 mV (0 %).
+        assert_eq!(battery_percent(3400, LIPO_CURVE), 2);
+    }
+
+    #[test]
+    fn test_clamping() {
+        // While charging.
+        assert_eq!(battery_percent(4350, LIPO_CURVE), 100);
+        // Below the cut-off of the protection circuit.
+        assert_eq!(battery_percent(3000, LIPO_CURVE), 0);
+        // No battery on the input.
+        assert_eq!(battery_percent(0, LIPO_CURVE), 0);
+    }
+}
@@ -0,0 +1,209 @@
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Poll, Waker};
+use critical_section::Mutex;
+
+/// Typed publish/subscribe topic with up to `SUBSCRIBERS` subscribers.
+///
+/// Every subscriber gets its own queue of `DEPTH` events. Publishing never
+/// blocks: if a subscriber's queue is full, the event is dropped for that
+/// subscriber and its overflow counter is incremented. Events published
+/// before a subscriber subscribed are not delivered to it.
+pub struct Topic<Event, const SUBSCRIBERS: usize, const DEPTH: usize> {
+    name: &'static str,
+    subscribers: AtomicUsize,
+    queues: [Mutex<RefCell<Queue<Event, DEPTH>>>; SUBSCRIBERS],
+}
+
+struct Queue<Event, const DEPTH: usize> {
+    events: [Option<Event>; DEPTH],
+    head: usize,
+    len: usize,
+    overflows: u32,
+    waker: Option<Waker>,
+}
+
+impl<Event, const DEPTH: usize> Queue<Event, DEPTH> {
+    const fn new() -> Self {
+        Queue {
+            events: [const { None }; DEPTH],
+            head: 0,
+            len: 0,
+            overflows: 0,
+            waker: None,
+        }
+    }
+
+    fn push(&mut self, event: Event) -> Option<Waker> {
+        if self.len == DEPTH {
+            self.overflows = self.overflows.saturating_add(1);
+            return None;
+        }
+        self.events[(self.head + self.len) % DEPTH] = Some(event);
+        self.len += 1;
+        self.waker.take()
+    }
+
+    fn pop(&mut self) -> Option<Event> {
+        let event = self.events[self.head].take()?;
+        self.head = (self.head + 1) % DEPTH;
+        self.len -= 1;
+        Some(event)
+    }
+}
+
+impl<Event: Clone, const SUBSCRIBERS: usize, const DEPTH: usize> Topic<Event, SUBSCRIBERS, DEPTH> {
+    pub const fn new(name: &'static str) -> Self {
+        const { assert!(DEPTH > 0, "topics need a queue depth of at least one") }
+        Topic {
+            name,
+            subscribers: AtomicUsize::new(0),
+            queues: [const { Mutex::new(RefCell::new(Queue::new())) }; SUBSCRIBERS],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns `None` if all subscriber slots are taken.
+    pub fn subscribe(&self) -> Option<Subscriber<'_, Event, DEPTH>> {
+        let index = self
+            .subscribers
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |subscribers| {
+                (subscribers < SUBSCRIBERS).then_some(subscribers + 1)
+            })
+            .ok()?;
+        Some(Subscriber {
+            queue: &self.queues[index],
+        })
+    }
+
+    /// Delivers the event to all current subscribers.
+    pub fn publish(&self, event: Event) {
+        let subscribers = self.subscribers.load(Ordering::Acquire);
+        for queue in &self.queues[..subscribers] {
+            let waker = critical_section::with(|cs| queue.borrow_ref_mut(cs).push(event.clone()));
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.subscribers.load(Ordering::Relaxed)
+    }
+
+    /// Events dropped across all subscribers because their queue was full.
+    pub fn overflows(&self) -> u32 {
+        critical_section::with(|cs| {
+            self.queues
+                .iter()
+                .map(|queue| queue.borrow_ref(cs).overflows)
+                .fold(0, u32::saturating_add)
+        })
+    }
+}
+
+/// Receiving end of a topic. Subscriptions last for the rest of the program.
+pub struct Subscriber<'a, Event, const DEPTH: usize> {
+    queue: &'a Mutex<RefCell<Queue<Event, DEPTH>>>,
+}
+
+impl<Event, const DEPTH: usize> Subscriber<'_, Event, DEPTH> {
+    pub fn try_next(&mut self) -> Option<Event> {
+        critical_section::with(|cs| self.queue.borrow_ref_mut(cs).pop())
+    }
+
+    /// Waits for the next event.
+    pub async fn next(&mut self) -> Event {
+        poll_fn(|cx| {
+            critical_section::with(|cs| {
+                let mut queue = self.queue.borrow_ref_mut(cs);
+                match queue.pop() {
+                    Some(event) => Poll::Ready(event),
+                    None => {
+                        match &mut queue.waker {
+                            Some(waker) => waker.clone_from(cx.waker()),
+                            None => queue.waker = Some(cx.waker().clone()),
+                        }
+                        Poll::Pending
+                    }
+                }
+            })
+        })
+        .await
+    }
+
+    /// Events dropped for this subscriber because its queue was full.
+    pub fn overflows(&self) -> u32 {
+        critical_section::with(|cs| self.queue.borrow_ref(cs).overflows)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::Context;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum TestEvent {
+        Up,
+        Down,
+    }
+
+    #[derive(Default)]
+    struct WakeCount(AtomicUsize);
+
+    impl Wake for WakeCount {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_publish_subscribe() {
+        static TOPIC: Topic<TestEvent, 2, 2> = Topic::new("test");
+        TOPIC.publish(TestEvent::Down);
+
+        let mut a = TOPIC.subscribe().unwrap();
+        let mut b = TOPIC.subscribe().unwrap();
+        assert!(TOPIC.subscribe().is_none());
+        assert_eq!(a.try_next(), None);
+
+        TOPIC.publish(TestEvent::Up);
+        TOPIC.publish(TestEvent::Down);
+        assert_eq!(a.try_next(), Some(TestEvent::Up));
+        TOPIC.publish(TestEvent::Up);
+        assert_eq!(a.try_next(), Some(TestEvent::Down));
+        assert_eq!(a.try_next(), Some(TestEvent::Up));
+        assert_eq!(a.overflows(), 0);
+
+        assert_eq!(b.overflows(), 1);
+        assert_eq!(TOPIC.overflows(), 1);
+        assert_eq!(b.try_next(), Some(TestEvent::Up));
+        assert_eq!(b.try_next(), Some(TestEvent::Down));
+        assert_eq!(b.try_next(), None);
+    }
+
+    #[test]
+    fn test_next() {
+        static TOPIC: Topic<TestEvent, 1, 1> = Topic::new("test");
+        let mut subscriber = TOPIC.subscribe().unwrap();
+        let wakes = Arc::new(WakeCount::default());
+        let waker = Waker::from(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut next = pin!(subscriber.next());
+        assert!(next.as_mut().poll(&mut cx).is_pending());
+        TOPIC.publish(TestEvent::Up);
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        assert_eq!(next.as_mut().poll(&mut cx), Poll::Ready(TestEvent::Up));
+    }
+}
@@ -0,0 +1,40 @@
+use core::ops::Range;
+
+/// Splits a full-duplex transfer of `read` and `write` bytes into pieces of
+/// at most `max_len` bytes each way. Once the shorter side is done its ranges
+/// are empty, e.g. an SPI master then clocks out filler bytes.
+pub fn chunks(
+    read: usize,
+    write: usize,
+    max_len: usize,
+) -> impl Iterator<Item = (Range<usize>, Range<usize>)> {
+    (0..read.max(write)).step_by(max_len).map(move |start| {
+        (
+            start.min(read)..(start + max_len).min(read),
+            start.min(write)..(start + max_len).min(write),
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chunks() {
+        let chunks = |read, write, max_len| chunks(read, write, max_len).collect::<Vec<_>>();
+
+        assert_eq!(chunks(0, 0, 4), []);
+        assert_eq!(chunks(3, 3, 4), [(0..3, 0..3)]);
+        assert_eq!(chunks(8, 8, 4), [(0..4, 0..4), (4..8, 4..8)]);
+        // Only the last chunk is short.
+        assert_eq!(chunks(0, 9, 4), [(0..0, 0..4), (0..0, 4..8), (0..0, 8..9)]);
+        // The write is done first.
+        assert_eq!(
+            chunks(10, 5, 4),
+            [(0..4, 0..4), (4..8, 4..5), (8..10, 5..5)]
+        );
+        // The read is done first.
+        assert_eq!(chunks(2, 6, 4), [(0..2, 0..4), (2..2, 4..6)]);
+    }
+}
@@ -0,0 +1,470 @@
+use super::registry::SingletonDescriptor;
+use super::{DeinitError, Deinitialized, InitError, Initialized};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+pub trait Driver: Deinitialized {
+    const DESCRIPTOR: DriverDescriptor;
+
+    /// Counts the initialized drivers that depend on this one.
+    fn dependents() -> &'static Dependents;
+}
+
+/// Number of initialized drivers which depend on a driver. A driver with
+/// dependents refuses to be deinitialized.
+pub struct Dependents(AtomicUsize);
+
+impl Dependents {
+    pub const fn new() -> Self {
+        Dependents(AtomicUsize::new(0))
+    }
+
+    pub fn acquire(&self) {
+        self.0.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn release(&self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+impl Default for Dependents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Identity of a driver. Descriptors are consts and copied wherever they are
+/// used, so drivers are told apart by the address of their `DriverId`
+/// static. The name is only for reports.
+///
+/// Const evaluation cannot compare addresses, `InitOrder::resolve()`
+/// compares the names instead. `#[driver]` names drivers by the path of
+/// their type, hand-written names must be unique as well.
+pub struct DriverId {
+    name: &'static str,
+}
+
+impl DriverId {
+    pub const fn new(name: &'static str) -> Self {
+        Self { name }
+    }
+}
+
+/// Static description of a driver and the drivers it depends on.
+///
+/// Descriptors generated by `#[driver]` reference each other by value, so a
+/// dependency cycle between them fails to compile.
+pub struct DriverDescriptor {
+    id: &'static DriverId,
+    depends_on: &'static [&'static DriverDescriptor],
+    after: &'static [&'static DriverDescriptor],
+    state: Option<&'static SingletonDescriptor>,
+    init: fn() -> Result<(), InitError>,
+    deinit: fn() -> Result<(), DeinitError>,
+    is_initialized: fn() -> bool,
+}
+
+impl DriverDescriptor {
+    pub const fn new(
+        id: &'static DriverId,
+        depends_on: &'static [&'static DriverDescriptor],
+        after: &'static [&'static DriverDescriptor],
+        state: Option<&'static SingletonDescriptor>,
+        init: fn() -> Result<(), InitError>,
+        deinit: fn() -> Result<(), DeinitError>,
+        is_initialized: fn() -> bool,
+    ) -> Self {
+        Self {
+            id,
+            depends_on,
+            after,
+            state,
+            init,
+            deinit,
+            is_initialized,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.id.name
+    }
+
+    pub const fn depends_on(&self) -> &'static [&'static DriverDescriptor] {
+        self.depends_on
+    }
+
+    /// Drivers that are initialized first without being required, e.g.
+    /// logging. A failure of one of them does not skip this driver.
+    pub const fn after(&self) -> &'static [&'static DriverDescriptor] {
+        self.after
+    }
+
+    /// The singleton holding the driver state, if any.
+    pub const fn state(&self) -> Option<&'static SingletonDescriptor> {
+        self.state
+    }
+
+    pub fn try_init(&self) -> Result<(), InitError> {
+        (self.init)()
+    }
+
+    pub fn deinit(&self) -> Result<(), DeinitError> {
+        (self.deinit)()
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        (self.is_initialized)()
+    }
+
+    /// Whether both describe the same driver.
+    pub fn is(&self, other: &DriverDescriptor) -> bool {
+        ptr::eq(self.id, other.id)
+    }
+
+    /// Like `is()` at compile time, see `DriverId`.
+    const fn is_const(&self, other: &DriverDescriptor) -> bool {
+        let (a, b) = (self.id.name.as_bytes(), other.id.name.as_bytes());
+        if a.len() != b.len() {
+            return false;
+        }
+        let mut i = 0;
+        while i < a.len() {
+            if a[i] != b[i] {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+}
+
+pub trait StatelessDriver: Driver {
+    fn init_state() -> &'static InitState;
+}
+
+pub struct InitState(AtomicBool);
+
+impl InitState {
+    pub const fn new() -> Self {
+        InitState(AtomicBool::new(false))
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Release)
+    }
+}
+
+impl Default for InitState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Initialized for InitState {
+    fn init(&self) {
+        self.0.store(true, Ordering::Release)
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphError {
+    TooManyDrivers,
+    /// Only reachable with hand-written descriptors declared as `static`s,
+    /// which unlike consts may reference each other.
+    Cycle(&'static str),
+}
+
+/// Driver init order resolved from the declared dependencies: every driver
+/// comes after all of its dependencies and the drivers it is ordered after.
+pub struct InitOrder<const N: usize> {
+    drivers: DriverList<N>,
+}
+
+impl<const N: usize> InitOrder<N> {
+    /// Resolves the init order of the given drivers and their transitive
+    /// dependencies. Can be evaluated at compile time.
+    pub const fn resolve(drivers: &[&'static DriverDescriptor]) -> Result<Self, GraphError> {
+        let mut order = DriverList::new();
+        let mut path = DriverList::new();
+        let mut i = 0;
+        while i < drivers.len() {
+            if let Err(err) = Self::visit(drivers[i], &mut order, &mut path) {
+                return Err(err);
+            }
+            i += 1;
+        }
+        Ok(Self { drivers: order })
+    }
+
+    const fn visit(
+        driver: &'static DriverDescriptor,
+        order: &mut DriverList<N>,
+        path: &mut DriverList<N>,
+    ) -> Result<(), GraphError> {
+        if order.contains(driver) {
+            return Ok(());
+        }
+        if path.contains(driver) {
+            return Err(GraphError::Cycle(driver.name()));
+        }
+        if let Err(err) = path.push(driver) {
+            return Err(err);
+        }
+        let mut i = 0;
+        while i < driver.depends_on.len() {
+            if let Err(err) = Self::visit(driver.depends_on[i], order, path) {
+                return Err(err);
+            }
+            i += 1;
+        }
+        let mut i = 0;
+        while i < driver.after.len() {
+            if let Err(err) = Self::visit(driver.after[i], order, path) {
+                return Err(err);
+            }
+            i += 1;
+        }
+        path.pop();
+        order.push(driver)
+    }
+
+    pub fn len(&self) -> usize {
+        self.drivers.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.drivers.len == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'static DriverDescriptor> + Clone + '_ {
+        self.drivers.items[..self.drivers.len]
+            .iter()
+            .flatten()
+            .copied()
+    }
+
+    /// Initializes all drivers in order. Drivers whose dependencies did not
+    /// come up are skipped, failures do not stop the remaining drivers.
+    pub fn try_init_all(&self) -> InitReport<N> {
+        self.try_init_all_timed(|| 0)
+    }
+
+    /// Like `try_init_all()` but also records how long the init of each
+    /// driver took, measured with the free-running tick counter `now`.
+    pub fn try_init_all_timed<C: Fn() -> u32>(&self, now: C) -> InitReport<N> {
+        let mut report = InitReport {
+            entries: [None; N],
+            len: 0,
+        };
+        for driver in self.iter() {
+            let failed_dependency = driver
+                .depends_on
+                .iter()
+                .find(|dependency| !report.is_initialized(dependency));
+            let start = now();
+            let status = match failed_dependency {
+                Some(dependency) => InitStatus::Skipped {
+                    dependency: dependency.name(),
+                },
+                None => match driver.try_init() {
+                    Ok(()) => InitStatus::Initialized,
+                    Err(err) => InitStatus::Failed(err),
+                },
+            };
+            let ticks = now().wrapping_sub(start);
+            report.entries[report.len] = Some((driver, status, ticks));
+            report.len += 1;
+        }
+        report
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitStatus {
+    Initialized,
+    Failed(InitError),
+    Skipped { dependency: &'static str },
+}
+
+/// Outcome of initializing the drivers of an `InitOrder`.
+#[derive(Clone, Copy)]
+pub struct InitReport<const N: usize> {
+    entries: [Option<(&'static DriverDescriptor, InitStatus, u32)>; N],
+    len: usize,
+}
+
+impl<const N: usize> InitReport<N> {
+    pub fn iter(&self) -> impl Iterator<Item = (&'static DriverDescriptor, InitStatus)> + '_ {
+        self.entries[..self.len]
+            .iter()
+            .flatten()
+            .map(|&(driver, status, _)| (driver, status))
+    }
+
+    /// Ticks spent in the init of the given driver.
+    pub fn init_ticks(&self, driver: &DriverDescriptor) -> Option<u32> {
+        self.entries[..self.len]
+            .iter()
+            .flatten()
+            .find(|(entry, ..)| entry.is(driver))
+            .map(|&(.., ticks)| ticks)
+    }
+
+    pub fn status(&self, driver: &DriverDescriptor) -> Option<InitStatus> {
+        self.iter()
+            .find(|(entry, _)| entry.is(driver))
+            .map(|(_, status)| status)
+    }
+
+    pub fn is_initialized(&self, driver: &DriverDescriptor) -> bool {
+        self.status(driver) == Some(InitStatus::Initialized)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.iter()
+            .all(|(_, status)| status == InitStatus::Initialized)
+    }
+}
+
+struct DriverList<const N: usize> {
+    items: [Option<&'static DriverDescriptor>; N],
+    len: usize,
+}
+
+impl<const N: usize> DriverList<N> {
+    const fn new() -> Self {
+        Self {
+            items: [None; N],
+            len: 0,
+        }
+    }
+
+    const fn contains(&self, driver: &DriverDescriptor) -> bool {
+        let mut i = 0;
+        while i < self.len {
+            if let Some(item) = self.items[i] {
+                if item.is_const(driver) {
+                    return true;
+                }
+            }
+            i += 1;
+        }
+        false
+    }
+
+    const fn push(&mut self, driver: &'static DriverDescriptor) -> Result<(), GraphError> {
+        if self.len == N {
+            return Err(GraphError::TooManyDrivers);
+        }
+        self.items[self.len] = Some(driver);
+        self.len += 1;
+        Ok(())
+    }
+
+    const fn pop(&mut self) {
+        self.len -= 1;
+        self.items[self.len] = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::error::Error;
+    use core::fmt;
+
+    #[derive(Debug)]
+    struct NoProbe;
+
+    impl fmt::Display for NoProbe {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "no probe attached")
+        }
+    }
+
+    impl Error for NoProbe {}
+
+    static NO_PROBE: NoProbe = NoProbe;
+
+    static LOG_ID: DriverId = DriverId::new("Log");
+    static POWER_ID: DriverId = DriverId::new("Power");
+    static RNG_ID: DriverId = DriverId::new("Rng");
+    static OTHER_POWER_ID: DriverId = DriverId::new("Power");
+    static A_ID: DriverId = DriverId::new("A");
+    static B_ID: DriverId = DriverId::new("B");
+
+    const LOG: DriverDescriptor = DriverDescriptor::new(
+        &LOG_ID,
+        &[],
+        &[],
+        None,
+        || Err(InitError::Driver(&NO_PROBE)),
+        || Ok(()),
+        || false,
+    );
+    const POWER: DriverDescriptor =
+        DriverDescriptor::new(&POWER_ID, &[], &[&LOG], None, || Ok(()), || Ok(()), || true);
+    const RNG: DriverDescriptor =
+        DriverDescriptor::new(&RNG_ID, &[&POWER], &[], None, || Ok(()), || Ok(()), || true);
+
+    /// Same name as `POWER` but a different driver.
+    const OTHER_POWER: DriverDescriptor = DriverDescriptor::new(
+        &OTHER_POWER_ID,
+        &[],
+        &[],
+        None,
+        || Ok(()),
+        || Ok(()),
+        || true,
+    );
+
+    static A: DriverDescriptor =
+        DriverDescriptor::new(&A_ID, &[&B], &[], None, || Ok(()), || Ok(()), || true);
+    static B: DriverDescriptor =
+        DriverDescriptor::new(&B_ID, &[&A], &[], None, || Ok(()), || Ok(()), || true);
+
+    #[test]
+    fn test_after() {
+        let init_order = InitOrder::<3>::resolve(&[&RNG]).unwrap();
+        let names: Vec<_> = init_order.iter().map(DriverDescriptor::name).collect();
+        assert_eq!(names, ["Log", "Power", "Rng"]);
+
+        let report = init_order.try_init_all();
+        assert_eq!(
+            report.status(&LOG),
+            Some(InitStatus::Failed(InitError::Driver(&NO_PROBE)))
+        );
+        let Some(InitStatus::Failed(err)) = report.status(&LOG) else {
+            unreachable!()
+        };
+        assert!(err.driver_error::<NoProbe>().is_some());
+        assert!(report.is_initialized(&POWER));
+        assert!(report.is_initialized(&RNG));
+    }
+
+    #[test]
+    fn test_same_name() {
+        assert!(!POWER.is(&OTHER_POWER));
+        let init_order = InitOrder::<3>::resolve(&[&RNG]).unwrap();
+        let report = init_order.try_init_all();
+        assert!(report.is_initialized(&POWER));
+        assert_eq!(report.status(&OTHER_POWER), None);
+    }
+
+    #[test]
+    fn test_static_cycle() {
+        assert_eq!(
+            InitOrder::<2>::resolve(&[&A]).err(),
+            Some(GraphError::Cycle("A"))
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use core::future::poll_fn;
+use core::task::Poll;
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, MultiwriteNorFlash, NorFlash,
+    NorFlashErrorKind, ReadNorFlash,
+};
+use embedded_storage_async::nor_flash as asynch;
+
+/// Raw access to a region of NOR flash. Offsets are relative to the start
+/// of the region and have been checked by [`Flash`].
+pub trait FlashBackend {
+    /// Number of bytes written at once.
+    const WORD_SIZE: usize;
+
+    /// Number of bytes erased at once.
+    const PAGE_SIZE: usize;
+
+    /// Whether a word may be written again before its page is erased. Set it
+    /// together with [`MultiwriteBackend`]. Otherwise [`Flash`] only writes
+    /// to erased words.
+    const MULTIWRITE: bool = false;
+
+    fn capacity(&self) -> usize;
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]);
+
+    /// Sets all bits of the page to one.
+    fn erase_page(&mut self, offset: usize);
+
+    /// Clears the bits of the word that are zero in `word`, which is
+    /// `WORD_SIZE` bytes long.
+    fn write_word(&mut self, offset: usize, word: &[u8]);
+}
+
+/// Backends that allow words to be written more than once between erases,
+/// as long as the writes only clear bits. [`Flash`] implements
+/// [`MultiwriteNorFlash`] for them.
+pub trait MultiwriteBackend: FlashBackend {}
+
+/// NOR flash with page-erase/word-write semantics on top of a backend.
+/// Operations are bounds and alignment checked before they reach the
+/// backend.
+pub struct Flash<B> {
+    backend: B,
+}
+
+impl<B: FlashBackend> Flash<B> {
+    pub const fn new(backend: B) -> Self {
+        const {
+            assert!(
+                B::PAGE_SIZE % B::WORD_SIZE == 0,
+                "pages must hold whole words"
+            )
+        }
+        Flash { backend }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn into_backend(self) -> B {
+        self.backend
+    }
+
+    fn is_erased(&mut self, offset: usize, len: usize) -> bool {
+        (offset..offset + len).all(|offset| {
+            let mut byte = [0];
+            self.backend.read(offset, &mut byte);
+            byte[0] == 0xFF
+        })
+    }
+}
+
+/// Lets the other tasks of the same priority run before continuing.
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+impl<B: FlashBackend> ErrorType for Flash<B> {
+    type Error = NorFlashErrorKind;
+}
+
+impl<B: FlashBackend> ReadNorFlash for Flash<B> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind> {
+        check_read(self, offset, bytes.len())?;
+        self.backend.read(offset as usize, bytes);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.backend.capacity()
+    }
+}
+
+impl<B: FlashBackend> NorFlash for Flash<B> {
+    const WRITE_SIZE: usize = B::WORD_SIZE;
+    const ERASE_SIZE: usize = B::PAGE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
+        check_erase(self, from, to)?;
+        for offset in (from as usize..to as usize).step_by(B::PAGE_SIZE) {
+            self.backend.erase_page(offset);
+        }
+        Ok(())
+    }
+
+    /// Fails without writing anything if the backend does not allow
+    /// multiple writes and a word has been written since it was erased.
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+        check_write(self, offset, bytes.len())?;
+        if !B::MULTIWRITE && !self.is_erased(offset as usize, bytes.len()) {
+            return Err(NorFlashErrorKind::Other);
+        }
+        for (i, word) in bytes.chunks_exact(B::WORD_SIZE).enumerate() {
+            self.backend
+                .write_word(offset as usize + i * B::WORD_SIZE, word);
+        }
+        Ok(())
+    }
+}
+
+impl<B: MultiwriteBackend> MultiwriteNorFlash for Flash<B> {}
+
+impl<B: FlashBackend> asynch::ReadNorFlash for Flash<B> {
+    const READ_SIZE: usize = <Self as ReadNorFlash>::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind> {
+        ReadNorFlash::read(self, offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        ReadNorFlash::capacity(self)
+    }
+}
+
+/// Backends block while they erase or program, so erases and writes go one
+/// page at a time and yield in between. The executor only stalls for a
+/// single page.
+impl<B: FlashBackend> asynch::NorFlash for Flash<B> {
+    const WRITE_SIZE: usize = <Self as NorFlash>::WRITE_SIZE;
+    const ERASE_SIZE: usize = <Self as NorFlash>::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
+        check_erase(self, from, to)?;
+        for page in (from..to).step_by(B::PAGE_SIZE) {
+            NorFlash::erase(self, page, page + B::PAGE_SIZE as u32)?;
+            yield_now().await;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+        check_write(self, offset, bytes.len())?;
+        if !B::MULTIWRITE && !self.is_erased(offset as usize, bytes.len()) {
+            return Err(NorFlashErrorKind::Other);
+        }
+        // Chunks end at page boundaries.
+        let mut offset = offset as usize;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let len = bytes.len().min(B::PAGE_SIZE - offset % B::PAGE_SIZE);
+            let (chunk, rest) = bytes.split_at(len);
+            NorFlash::write(self, offset as u32, chunk)?;
+            offset += len;
+            bytes = rest;
+            yield_now().await;
+        }
+        Ok(())
+    }
+}
+
+/// RAM-backed fake of a flash region for host tests.
+pub struct RamFlash<const SIZE: usize, const WORD: usize, const PAGE: usize> {
+    bytes: [u8; SIZE],
+    erases: usize,
+}
+
+impl<const SIZE: usize, const WORD: usize, const PAGE: usize> RamFlash<SIZE, WORD, PAGE> {
+    /// Starts out erased.
+    pub const fn new() -> Self {
+        RamFlash {
+            bytes: [0xFF; SIZE],
+            erases: 0,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8; SIZE] {
+        &self.bytes
+    }
+
+    /// Number of pages erased so far.
+    pub fn erases(&self) -> usize {
+        self.erases
+    }
+}
+
+impl<const SIZE: usize, const WORD: usize, const PAGE: usize> Default
+    for RamFlash<SIZE, WORD, PAGE>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize, const WORD: usize, const PAGE: usize> FlashBackend
+    for RamFlash<SIZE, WORD, PAGE>
+{
+    const WORD_SIZE: usize = WORD;
+    const PAGE_SIZE: usize = PAGE;
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) {
+        bytes.copy_from_slice(&self.bytes[offset..offset + bytes.len()]);
+    }
+
+    fn erase_page(&mut self, offset: usize) {
+        self.bytes[offset..offset + PAGE].fill(0xFF);
+        self.erases += 1;
+    }
+
+    fn write_word(&mut self, offset: usize, word: &[u8]) {
+        for (byte, &value) in self.bytes[offset..offset + WORD].iter_mut().zip(word) {
+            *byte &= value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    type TestFlash = Flash<RamFlash<64, 4, 16>>;
+
+    #[test]
+    fn test_erase_write_read() {
+        let mut flash = TestFlash::new(RamFlash::new());
+        assert_eq!(ReadNorFlash::capacity(&flash), 64);
+
+        NorFlash::write(&mut flash, 16, &[0x12, 0x34, 0x56, 0x78]).unwrap();
+        let mut bytes = [0; 6];
+        ReadNorFlash::read(&mut flash, 15, &mut bytes).unwrap();
+        assert_eq!(bytes, [0xFF, 0x12, 0x34, 0x56, 0x78, 0xFF]);
+
+        NorFlash::erase(&mut flash, 16, 48).unwrap();
+        assert_eq!(flash.backend().erases(), 2);
+        assert!(flash.backend().bytes().iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn test_write_requires_erase() {
+        let mut flash = TestFlash::new(RamFlash::new());
+        NorFlash::write(&mut flash, 20, &[0xF0, 0xFF, 0xFF, 0xFF]).unwrap();
+
+        // The second word is erased but the first one is not, nothing is
+        // written.
+        assert_eq!(
+            NorFlash::write(&mut flash, 16, &[0; 8]),
+            Err(NorFlashErrorKind::Other)
+        );
+        assert_eq!(
+            NorFlash::write(&mut flash, 20, &[0; 4]),
+            Err(NorFlashErrorKind::Other)
+        );
+        let mut bytes = [0; 8];
+        ReadNorFlash::read(&mut flash, 16, &mut bytes).unwrap();
+        assert_eq!(bytes, [0xFF, 0xFF, 0xFF, 0xFF, 0xF0, 0xFF, 0xFF, 0xFF]);
+
+        NorFlash::erase(&mut flash, 16, 32).unwrap();
+        NorFlash::write(&mut flash, 16, &[0; 8]).unwrap();
+    }
+
+    #[test]
+    fn test_bounds_and_alignment() {
+        let mut flash = TestFlash::new(RamFlash::new());
+        let mut bytes = [0; 4];
+        assert_eq!(
+            ReadNorFlash::read(&mut flash, 61, &mut bytes),
+            Err(NorFlashErrorKind::OutOfBounds)
+        );
+        assert_eq!(
+            NorFlash::write(&mut flash, 2, &bytes),
+            Err(NorFlashErrorKind::NotAligned)
+        );
+        assert_eq!(
+            NorFlash::write(&mut flash, 64, &bytes),
+            Err(NorFlashErrorKind::OutOfBounds)
+        );
+        assert_eq!(
+            NorFlash::erase(&mut flash, 0, 8),
+            Err(NorFlashErrorKind::NotAligned)
+        );
+        assert_eq!(
+            NorFlash::erase(&mut flash, 32, 16),
+            Err(NorFlashErrorKind::OutOfBounds)
+        );
+        assert_eq!(
+            NorFlash::erase(&mut flash, 48, 80),
+            Err(NorFlashErrorKind::OutOfBounds)
+        );
+        assert_eq!(flash.backend().erases(), 0);
+    }
+
+    #[test]
+    fn test_async() {
+        let mut flash = TestFlash::new(RamFlash::new());
+        let mut cx = Context::from_waker(Waker::noop());
+
+        // Yields after each page.
+        {
+            let mut write = pin!(asynch::NorFlash::write(&mut flash, 12, &[1; 8]));
+            assert_eq!(write.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(write.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(write.poll(&mut cx), Poll::Ready(Ok(())));
+        }
+        let mut bytes = [0; 10];
+        {
+            let read = pin!(asynch::ReadNorFlash::read(&mut flash, 11, &mut bytes));
+            assert_eq!(read.poll(&mut cx), Poll::Ready(Ok(())));
+        }
+        assert_eq!(bytes, [0xFF, 1, 1, 1, 1, 1, 1, 1, 1, 0xFF]);
+
+        {
+            let mut erase = pin!(asynch::NorFlash::erase(&mut flash, 0, 32));
+            assert_eq!(erase.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(erase.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(erase.poll(&mut cx), Poll::Ready(Ok(())));
+        }
+        assert_eq!(flash.backend().erases(), 2);
+    }
+}
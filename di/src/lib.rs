@@ -1,8 +1,23 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
+pub mod battery;
+pub mod bus;
+pub mod dma;
+pub mod driver;
+#[cfg(feature = "flash")]
+pub mod flash;
+pub mod lock;
+pub mod pool;
+pub mod registry;
+pub mod resources;
 pub mod singleton;
 pub mod token;
+mod wait_queue;
+
+use core::error::Error;
+use core::fmt;
+use core::ptr;
+use resources::ClaimError;
 
 pub trait Initialized {
     fn init(&self);
@@ -16,6 +31,170 @@ pub trait Initialized {
     }
 }
 
+/// Why a driver or singleton could not be initialized.
+#[derive(Debug, Clone, Copy)]
+pub enum InitError {
+    AlreadyInitialized,
+    /// A driver listed in `depends_on(...)` is not initialized.
+    Dependency {
+        driver: &'static str,
+        dependency: &'static str,
+    },
+    Claim(ClaimError),
+    /// Reported by the driver itself. The concrete error can be recovered
+    /// with `driver_error()`.
+    Driver(&'static (dyn Error + Sync)),
+}
+
+impl InitError {
+    pub fn driver_error<E: Error + 'static>(&self) -> Option<&'static E> {
+        match *self {
+            InitError::Driver(err) => (err as &'static dyn Error).downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Driver errors are equal if they are the same static.
+impl PartialEq for InitError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (InitError::AlreadyInitialized, InitError::AlreadyInitialized) => true,
+            (
+                InitError::Dependency { driver, dependency },
+                InitError::Dependency {
+                    driver: other_driver,
+                    dependency: other_dependency,
+                },
+            ) => (driver, dependency) == (other_driver, other_dependency),
+            (InitError::Claim(err), InitError::Claim(other)) => err == other,
+            (InitError::Driver(err), InitError::Driver(other)) => ptr::addr_eq(*err, *other),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for InitError {}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::AlreadyInitialized => write!(f, "InitError: already initialized."),
+            InitError::Dependency { driver, dependency } => write!(
+                f,
+                "InitError: {driver} depends on uninitialized {dependency}."
+            ),
+            InitError::Claim(err) => err.fmt(f),
+            InitError::Driver(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for InitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InitError::Claim(err) => Some(err),
+            InitError::Driver(err) => Some(*err),
+            _ => None,
+        }
+    }
+}
+
+pub trait TryInitialized {
+    /// Fails with `InitError::AlreadyInitialized` if already initialized.
+    fn try_init(&self) -> Result<(), InitError>;
+
+    fn is_initialized(&self) -> bool;
+
+    fn try_ensure_is_initialized(&self) -> Result<(), InitError> {
+        if self.is_initialized() {
+            Ok(())
+        } else {
+            self.try_init()
+        }
+    }
+}
+
+pub trait Deinitialized: TryInitialized {
+    /// Tears down the instance so that it can be initialized again later.
+    /// Does nothing if it is not initialized.
+    fn deinit(&self) -> Result<(), DeinitError>;
+}
+
+/// Why a driver could not be deinitialized.
+#[derive(Debug, Clone, Copy)]
+pub enum DeinitError {
+    /// Initialized drivers still depend on the driver.
+    InUse {
+        driver: &'static str,
+        dependents: usize,
+    },
+    /// An async task holds the driver's state through `Singleton::lock()`.
+    Locked { driver: &'static str },
+    /// Reported by the driver's deinit hook, the driver stays up. The
+    /// concrete error can be recovered with `driver_error()`.
+    Driver(&'static (dyn Error + Sync)),
+}
+
+impl DeinitError {
+    pub fn driver_error<E: Error + 'static>(&self) -> Option<&'static E> {
+        match *self {
+            DeinitError::Driver(err) => (err as &'static dyn Error).downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Driver errors are equal if they are the same static.
+impl PartialEq for DeinitError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                DeinitError::InUse { driver, dependents },
+                DeinitError::InUse {
+                    driver: other_driver,
+                    dependents: other_dependents,
+                },
+            ) => (driver, dependents) == (other_driver, other_dependents),
+            (DeinitError::Locked { driver }, DeinitError::Locked { driver: other }) => {
+                driver == other
+            }
+            (DeinitError::Driver(err), DeinitError::Driver(other)) => ptr::addr_eq(*err, *other),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for DeinitError {}
+
+impl fmt::Display for DeinitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeinitError::InUse { driver, dependents } => write!(
+                f,
+                "DeinitError: {driver} is still used by {dependents} driver(s)."
+            ),
+            DeinitError::Locked { driver } => {
+                write!(f, "DeinitError: {driver} is locked by an async task.")
+            }
+            DeinitError::Driver(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for DeinitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DeinitError::Driver(err) => Some(*err),
+            _ => None,
+        }
+    }
+}
+
 pub trait WithDependency<Dependency> {
-    fn with_dependency<Result, F: FnOnce(Dependency) -> Result>(f: F) -> Result;
+    /// Hands the dependency to `f`. Fails with `InitError::Claim` if any part
+    /// of it has already been claimed.
+    fn with_dependency<Result, F: FnOnce(Dependency) -> Result>(
+        f: F,
+    ) -> core::result::Result<Result, InitError>;
 }
@@ -0,0 +1,98 @@
+/// Guards singleton content against concurrent access.
+///
+/// # Safety
+///
+/// `lock` must run `f` with exclusive access to all data guarded by this lock
+/// with respect to every context that may access it.
+pub unsafe trait Lock {
+    fn lock<Result, F: FnOnce() -> Result>(f: F) -> Result;
+}
+
+/// Masks all interrupts through the global critical section.
+pub struct CriticalSectionLock;
+
+// SAFETY: A critical section excludes all other contexts on the core.
+unsafe impl Lock for CriticalSectionLock {
+    fn lock<Result, F: FnOnce() -> Result>(f: F) -> Result {
+        critical_section::with(|_| f())
+    }
+}
+
+/// Masks interrupts up to the logical priority `CEILING` through BASEPRI,
+/// the scheme RTIC uses for shared resources. Interrupts above the ceiling
+/// stay responsive.
+///
+/// `CEILING` must be at least the priority of every context accessing the
+/// guarded singleton. Taking the lock from a context above the ceiling,
+/// including core exceptions, panics as that context could preempt the
+/// holder of the lock.
+#[cfg(feature = "cortex-m")]
+pub struct CeilingLock<const CEILING: u8, const NVIC_PRIO_BITS: u8>;
+
+/// Interrupt number of the active interrupt.
+#[cfg(feature = "cortex-m")]
+#[derive(Clone, Copy)]
+struct ActiveInterrupt(u16);
+
+// SAFETY: Only built from the number of the active interrupt.
+#[cfg(feature = "cortex-m")]
+unsafe impl cortex_m::interrupt::InterruptNumber for ActiveInterrupt {
+    fn number(self) -> u16 {
+        self.0
+    }
+}
+
+/// Logical priority of the running context as RTIC counts it: 0 in thread
+/// mode and 1 up to `2^NVIC_PRIO_BITS` in interrupts. Core exceptions rank
+/// above all interrupts.
+#[cfg(feature = "cortex-m")]
+fn current_priority<const NVIC_PRIO_BITS: u8>() -> u16 {
+    use cortex_m::peripheral::scb::VectActive;
+    use cortex_m::peripheral::{NVIC, SCB};
+
+    match SCB::vect_active() {
+        VectActive::ThreadMode => 0,
+        VectActive::Interrupt { irqn } => {
+            let hardware = NVIC::get_priority(ActiveInterrupt(u16::from(irqn)));
+            (1 << NVIC_PRIO_BITS) - u16::from(hardware >> (8 - NVIC_PRIO_BITS))
+        }
+        VectActive::Exception(_) => u16::MAX,
+    }
+}
+
+// SAFETY: Single-core only. Contexts above the ceiling are refused, raising
+// BASEPRI to the ceiling excludes all other contexts. The highest priority
+// cannot be masked by BASEPRI and falls back to disabling interrupts.
+#[cfg(feature = "cortex-m")]
+unsafe impl<const CEILING: u8, const NVIC_PRIO_BITS: u8> Lock
+    for CeilingLock<CEILING, NVIC_PRIO_BITS>
+{
+    fn lock<Result, F: FnOnce() -> Result>(f: F) -> Result {
+        use cortex_m::register::{basepri, basepri_max};
+
+        const {
+            assert!(
+                CEILING >= 1 && CEILING <= 1 << NVIC_PRIO_BITS,
+                "ceiling out of range"
+            )
+        };
+
+        if CEILING == 1 << NVIC_PRIO_BITS {
+            return cortex_m::interrupt::free(|_| f());
+        }
+
+        assert!(
+            current_priority::<NVIC_PRIO_BITS>() <= u16::from(CEILING),
+            "lock taken above its ceiling"
+        );
+
+        let ceiling = ((1 << NVIC_PRIO_BITS) - CEILING) << (8 - NVIC_PRIO_BITS);
+        let current = basepri::read();
+        // Only ever raises the masked priority, so nested locks are cheap.
+        basepri_max::write(ceiling);
+        let result = f();
+        // SAFETY: Restores the mask that was active when the lock was taken.
+        unsafe { basepri::write(current) };
+        result
+    }
+}
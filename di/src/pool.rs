@@ -0,0 +1,201 @@
+use core::cell::UnsafeCell;
+use core::mem::{self, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Statically allocated pool of `N` slots holding values of type `T`.
+///
+/// Pools are meant to be declared as `static`s. This places them in RAM, so
+/// that buffers handed out by a pool can be used with EasyDMA.
+pub struct Pool<T, const N: usize> {
+    name: &'static str,
+    slots: [Slot<T>; N],
+}
+
+struct Slot<T> {
+    taken: AtomicBool,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Slot<T> {
+    const fn new() -> Self {
+        Slot {
+            taken: AtomicBool::new(false),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+}
+
+// SAFETY: A slot's value is only accessed by the unique owner of the slot.
+unsafe impl<T: Send, const N: usize> Sync for Pool<T, N> {}
+
+impl<T, const N: usize> Pool<T, N> {
+    pub const fn new(name: &'static str) -> Self {
+        Pool {
+            name,
+            slots: [const { Slot::new() }; N],
+        }
+    }
+
+    /// Moves `value` into a free slot. Returns `None` if the pool is
+    /// exhausted.
+    pub fn alloc(&'static self, value: T) -> Option<PoolBox<T>> {
+        self.alloc_with(|slot| {
+            slot.write(value);
+        })
+    }
+
+    /// Claims a free slot and initializes it in place with `init`, which
+    /// must fully initialize the slot.
+    fn alloc_with<F: FnOnce(&mut MaybeUninit<T>)>(&'static self, init: F) -> Option<PoolBox<T>> {
+        let slot = self.slots.iter().find(|slot| {
+            slot.taken
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })?;
+        // SAFETY: The slot was free and is now exclusively owned by us.
+        init(unsafe { &mut *slot.value.get() });
+        Some(PoolBox { slot })
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn available(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| !slot.taken.load(Ordering::Relaxed))
+            .count()
+    }
+}
+
+impl<const SIZE: usize, const N: usize> Pool<[u8; SIZE], N> {
+    /// Hands out a zeroed byte buffer without copying it through the stack.
+    pub fn alloc_zeroed(&'static self) -> Option<PoolBox<[u8; SIZE]>> {
+        self.alloc_with(|slot| {
+            // SAFETY: All-zero bytes are a valid byte array.
+            unsafe { ptr::write_bytes(slot.as_mut_ptr(), 0, 1) };
+        })
+    }
+}
+
+/// Type-erased view of a pool for reporting.
+pub trait PoolInfo: Sync {
+    fn name(&self) -> &'static str;
+
+    fn capacity(&self) -> usize;
+
+    fn available(&self) -> usize;
+
+    /// Static memory reserved by the pool in bytes.
+    fn reserved(&self) -> usize;
+}
+
+impl<T: Send, const N: usize> PoolInfo for Pool<T, N> {
+    fn name(&self) -> &'static str {
+        Pool::name(self)
+    }
+
+    fn capacity(&self) -> usize {
+        N
+    }
+
+    fn available(&self) -> usize {
+        Pool::available(self)
+    }
+
+    fn reserved(&self) -> usize {
+        mem::size_of::<Self>()
+    }
+}
+
+/// Owned pool slot. The value is dropped and the slot returned to the pool
+/// when the box is dropped.
+pub struct PoolBox<T: 'static> {
+    slot: &'static Slot<T>,
+}
+
+// SAFETY: The box owns the value in the slot.
+unsafe impl<T: Send> Send for PoolBox<T> {}
+unsafe impl<T: Sync> Sync for PoolBox<T> {}
+
+impl<T> PoolBox<T> {
+    /// Keeps the slot for the rest of the program, e.g. for buffers that
+    /// must outlive the driver they were handed to.
+    pub fn leak(self) -> &'static mut T {
+        let slot = self.slot;
+        mem::forget(self);
+        // SAFETY: The slot is initialized and never returned to the pool.
+        unsafe { (*slot.value.get()).assume_init_mut() }
+    }
+}
+
+impl<T> Deref for PoolBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The slot is initialized and owned by this box.
+        unsafe { (*self.slot.value.get()).assume_init_ref() }
+    }
+}
+
+impl<T> DerefMut for PoolBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: See above.
+        unsafe { (*self.slot.value.get()).assume_init_mut() }
+    }
+}
+
+impl<T> Drop for PoolBox<T> {
+    fn drop(&mut self) {
+        // SAFETY: The slot is initialized and owned by this box.
+        unsafe { (*self.slot.value.get()).assume_init_drop() };
+        self.slot.taken.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_alloc_and_drop() {
+        static POOL: Pool<[u8; 16], 2> = Pool::new("test");
+        assert!(POOL.reserved() >= 2 * 16);
+
+        let mut a = POOL.alloc_zeroed().unwrap();
+        a[0] = 1;
+        let b = POOL.alloc([2; 16]).unwrap();
+        assert!(POOL.alloc_zeroed().is_none());
+        assert_eq!((a[0], b[0]), (1, 2));
+
+        drop(a);
+        assert_eq!(POOL.available(), 1);
+        let c = POOL.alloc_zeroed().unwrap();
+        assert_eq!(c[0], 0);
+
+        let leaked = c.leak();
+        drop(b);
+        leaked[0] = 3;
+        assert_eq!(POOL.available(), 1);
+    }
+
+    #[test]
+    fn test_drop_value() {
+        use std::sync::Arc;
+
+        static POOL: Pool<Arc<()>, 1> = Pool::new("test");
+        let value = Arc::new(());
+        let boxed = POOL.alloc(value.clone()).unwrap();
+        assert_eq!(Arc::strong_count(&value), 2);
+        drop(boxed);
+        assert_eq!(Arc::strong_count(&value), 1);
+        assert_eq!(POOL.available(), 1);
+    }
+}
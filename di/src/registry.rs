@@ -0,0 +1,314 @@
+use super::driver::{DriverDescriptor, InitOrder, InitReport, InitStatus};
+use super::pool::PoolInfo;
+use super::token::Holders;
+use core::cell::{Cell, RefCell};
+use critical_section::Mutex;
+
+/// Singletons that were initialized at least once.
+pub(crate) static SINGLETONS: List<SingletonDescriptor> = List::new();
+
+/// Shared token types that were requested at least once.
+pub(crate) static TOKENS: List<Holders> = List::new();
+
+/// Entry of a `List`, embedded in the static it lists.
+pub(crate) struct Link<T: 'static> {
+    item: Cell<Option<&'static T>>,
+    next: Cell<Option<&'static Link<T>>>,
+}
+
+// SAFETY: Links are only written once, inside a critical section and before
+// they are published. They only point to `SingletonDescriptor` and `Holders`,
+// which are `Sync`.
+unsafe impl<T: 'static> Sync for Link<T> {}
+
+impl<T: 'static> Link<T> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            item: Cell::new(None),
+            next: Cell::new(None),
+        }
+    }
+}
+
+/// Intrusive list of statics that announce themselves at runtime, so that
+/// the registry does not depend on hand-maintained lists.
+pub(crate) struct List<T: 'static> {
+    head: Mutex<Cell<Option<&'static Link<T>>>>,
+}
+
+impl<T: 'static> List<T> {
+    const fn new() -> Self {
+        Self {
+            head: Mutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Adds `item` through its `link` unless it is already listed.
+    pub(crate) fn push(&self, link: &'static Link<T>, item: &'static T) {
+        critical_section::with(|cs| {
+            if link.item.get().is_some() {
+                return;
+            }
+            let head = self.head.borrow(cs);
+            link.item.set(Some(item));
+            link.next.set(head.get());
+            head.set(Some(link));
+        })
+    }
+
+    /// Most recently listed first.
+    fn iter(&self) -> impl Iterator<Item = &'static T> + Clone {
+        let mut next = critical_section::with(|cs| self.head.borrow(cs).get());
+        core::iter::from_fn(move || {
+            let link = next?;
+            next = link.next.get();
+            link.item.get()
+        })
+    }
+}
+
+/// Describes a singleton for introspection.
+pub struct SingletonDescriptor {
+    name: &'static str,
+    index: Option<usize>,
+    is_initialized: fn() -> bool,
+}
+
+impl SingletonDescriptor {
+    pub const fn new(name: &'static str, is_initialized: fn() -> bool) -> Self {
+        Self {
+            name,
+            index: None,
+            is_initialized,
+        }
+    }
+
+    /// Describes one instance of a multi-instance singleton.
+    pub const fn new_instance(
+        name: &'static str,
+        index: usize,
+        is_initialized: fn() -> bool,
+    ) -> Self {
+        Self {
+            name,
+            index: Some(index),
+            is_initialized,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The instance index of a multi-instance singleton.
+    pub const fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        (self.is_initialized)()
+    }
+}
+
+/// State of a driver as seen by the registry.
+#[derive(Clone, Copy)]
+pub struct DriverInfo {
+    pub descriptor: &'static DriverDescriptor,
+    /// Outcome of the init run, `None` before drivers were initialized.
+    pub status: Option<InitStatus>,
+    pub init_ticks: Option<u32>,
+}
+
+impl DriverInfo {
+    /// Whether the driver is initialized now, e.g. after a deinit.
+    pub fn is_initialized(&self) -> bool {
+        self.descriptor.is_initialized()
+    }
+}
+
+/// Knows all drivers, singletons, shared token types and buffer pools of the
+/// firmware and records the outcome of driver init so that it can be queried at runtime.
+///
+/// Singletons backing a driver are found through the driver. All other
+/// singletons are listed once they were initialized and shared token types
+/// once a token was first requested.
+pub struct Registry<const N: usize> {
+    init_order: &'static InitOrder<N>,
+    pools: &'static [&'static dyn PoolInfo],
+    report: Mutex<RefCell<Option<InitReport<N>>>>,
+}
+
+impl<const N: usize> Registry<N> {
+    pub const fn new(
+        init_order: &'static InitOrder<N>,
+        pools: &'static [&'static dyn PoolInfo],
+    ) -> Self {
+        Self {
+            init_order,
+            pools,
+            report: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Initializes all drivers, see `InitOrder::try_init_all_timed()`, and
+    /// records the report.
+    pub fn try_init_all<C: Fn() -> u32>(&self, now: C) -> InitReport<N> {
+        let report = self.init_order.try_init_all_timed(now);
+        critical_section::with(|cs| self.report.borrow_ref_mut(cs).replace(report));
+        report
+    }
+
+    pub fn report(&self) -> Option<InitReport<N>> {
+        critical_section::with(|cs| *self.report.borrow_ref(cs))
+    }
+
+    /// All drivers in init order.
+    pub fn drivers(&self) -> impl Iterator<Item = DriverInfo> + '_ {
+        let report = self.report();
+        self.init_order.iter().map(move |descriptor| DriverInfo {
+            descriptor,
+            status: report.as_ref().and_then(|report| report.status(descriptor)),
+            init_ticks: report
+                .as_ref()
+                .and_then(|report| report.init_ticks(descriptor)),
+        })
+    }
+
+    /// All singletons, each listed once even if shared by several drivers.
+    pub fn singletons(&self) -> impl Iterator<Item = &'static SingletonDescriptor> + '_ {
+        let all = self
+            .init_order
+            .iter()
+            .filter_map(DriverDescriptor::state)
+            .chain(SINGLETONS.iter());
+        all.clone()
+            .enumerate()
+            .filter(move |(i, singleton)| {
+                !all.clone().take(*i).any(|other| {
+                    (other.name(), other.index()) == (singleton.name(), singleton.index())
+                })
+            })
+            .map(|(_, singleton)| singleton)
+    }
+
+    pub fn tokens(&self) -> impl Iterator<Item = &'static Holders> + '_ {
+        TOKENS.iter()
+    }
+
+    pub fn pools(&self) -> impl Iterator<Item = &'static dyn PoolInfo> + '_ {
+        self.pools.iter().copied()
+    }
+
+    /// Static memory reserved by all pools in bytes.
+    pub fn reserved(&self) -> usize {
+        self.pools().map(PoolInfo::reserved).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::driver::DriverId;
+    use crate::pool::Pool;
+    use crate::resources::ClaimError;
+    use crate::singleton::{Singleton, SingletonHolderImpl};
+    use crate::token::Release;
+    use crate::{InitError, TryInitialized};
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    static STATE: SingletonDescriptor = SingletonDescriptor::new("State", || true);
+
+    struct Extra;
+
+    impl Singleton for Extra {
+        type Content = u32;
+        type Lock = crate::lock::CriticalSectionLock;
+
+        const DESCRIPTOR: SingletonDescriptor =
+            SingletonDescriptor::new("Extra", || Extra.is_initialized());
+
+        fn state_holder() -> &'static SingletonHolderImpl<u32> {
+            static STATE_HOLDER: SingletonHolderImpl<u32> = SingletonHolderImpl::new();
+            &STATE_HOLDER
+        }
+
+        fn try_new() -> Result<u32, InitError> {
+            Ok(0)
+        }
+    }
+
+    static A_ID: DriverId = DriverId::new("A");
+    static B_ID: DriverId = DriverId::new("B");
+
+    const A: DriverDescriptor =
+        DriverDescriptor::new(&A_ID, &[], &[], Some(&STATE), || Ok(()), || Ok(()), || true);
+    const B: DriverDescriptor = DriverDescriptor::new(
+        &B_ID,
+        &[&A],
+        &[],
+        Some(&STATE),
+        || Err(InitError::Claim(ClaimError("broken"))),
+        || Ok(()),
+        || false,
+    );
+    const INIT_ORDER: InitOrder<2> = match InitOrder::resolve(&[&B]) {
+        Ok(init_order) => init_order,
+        Err(_) => panic!(),
+    };
+
+    #[derive(Default)]
+    struct TestToken;
+
+    impl Release for TestToken {
+        fn release(&self) {}
+    }
+
+    crate::holders!(TestToken);
+
+    static REGISTRY: Registry<2> = Registry::new(&INIT_ORDER, &[&POOL]);
+
+    static POOL: Pool<[u8; 64], 2> = Pool::new("Pool");
+
+    #[test]
+    fn test_registry() {
+        assert!(REGISTRY.report().is_none());
+        assert!(REGISTRY.drivers().all(|driver| driver.status.is_none()));
+
+        static CLOCK: AtomicU32 = AtomicU32::new(0);
+        REGISTRY.try_init_all(|| CLOCK.fetch_add(5, Ordering::Relaxed));
+
+        let drivers: Vec<_> = REGISTRY.drivers().collect();
+        assert_eq!(drivers[0].descriptor.name(), "A");
+        assert_eq!(drivers[0].status, Some(InitStatus::Initialized));
+        assert_eq!(drivers[0].init_ticks, Some(5));
+        assert_eq!(drivers[1].descriptor.name(), "B");
+        assert_eq!(
+            drivers[1].status,
+            Some(InitStatus::Failed(InitError::Claim(ClaimError("broken"))))
+        );
+        assert!(!drivers[1].is_initialized());
+
+        // Other tests register their singletons and tokens too.
+        let singletons = || REGISTRY.singletons().map(|s| s.name()).collect::<Vec<_>>();
+        assert_eq!(singletons().first(), Some(&"State"));
+        assert!(!singletons().contains(&"Extra"));
+        Extra.try_init().unwrap();
+        Extra::deinit().unwrap();
+        Extra.try_init().unwrap();
+        assert_eq!(Extra.try_init(), Err(InitError::AlreadyInitialized));
+        let extra = singletons().iter().filter(|name| **name == "Extra").count();
+        assert_eq!(extra, 1);
+
+        let _token = crate::token::SharedToken::new(TestToken);
+        let token = REGISTRY
+            .tokens()
+            .find(|token| token.name().ends_with("::TestToken"))
+            .unwrap();
+        assert_eq!(token.count(), 1);
+
+        let _buffer = POOL.alloc_zeroed().unwrap();
+        let pool = REGISTRY.pools().next().unwrap();
+        assert_eq!((pool.name(), pool.available()), ("Pool", 1));
+        assert_eq!(REGISTRY.reserved(), pool.reserved());
+    }
+}
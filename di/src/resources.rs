@@ -0,0 +1,145 @@
+use super::singleton::Singleton;
+use super::{InitError, WithDependency};
+use core::error::Error;
+use core::fmt;
+
+/// Peripheral storage that hands out every peripheral it holds exactly once.
+///
+/// Usually derived with `#[derive(Resources)]` which checks at compile time
+/// that every peripheral is owned by exactly one driver.
+pub trait Resources {
+    const OWNERSHIP: &'static [Ownership];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ownership {
+    pub peripheral: &'static str,
+    pub owner: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClaimError(pub &'static str);
+
+impl fmt::Display for ClaimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ClaimError: {}.", self.0)
+    }
+}
+
+impl Error for ClaimError {}
+
+impl From<ClaimError> for InitError {
+    fn from(err: ClaimError) -> Self {
+        InitError::Claim(err)
+    }
+}
+
+/// Hands out a dependency held by resources.
+///
+/// Implemented for single peripherals by `#[derive(Resources)]` and for
+/// tuples of claimable parts, which are claimed all or nothing.
+pub trait Claim<Dependency> {
+    /// Checks that the dependency can be claimed without claiming it.
+    fn check(&self) -> Result<(), ClaimError>;
+
+    /// Claims the dependency. Panics if it has already been claimed.
+    fn claim(&mut self) -> Dependency;
+
+    fn restore(&mut self, dependency: Dependency);
+
+    fn try_claim(&mut self) -> Result<Dependency, ClaimError> {
+        <Self as Claim<Dependency>>::check(self)?;
+        Ok(<Self as Claim<Dependency>>::claim(self))
+    }
+}
+
+/// A struct dependency built from a tuple of parts, usually derived with
+/// `#[derive(Dependency)]`.
+pub trait Composite: Sized {
+    type Parts;
+
+    fn from_parts(parts: Self::Parts) -> Self;
+
+    fn into_parts(self) -> Self::Parts;
+}
+
+/// Declares the unique owner of a dependency held by a resources singleton.
+pub trait Owner<Dependency> {
+    type Resources: Singleton<Content: Claim<Dependency>>;
+
+    /// Returns a previously claimed dependency so that it can be claimed
+    /// again, e.g. after the owner has been deinitialized.
+    fn return_dependency(dependency: Dependency) {
+        Self::Resources::with_ref_mut(|resources| {
+            Claim::<Dependency>::restore(resources, dependency)
+        })
+    }
+
+    /// Like `WithDependency::with_dependency()` but reports the bare
+    /// `ClaimError`.
+    fn try_with_dependency<Result, F>(f: F) -> core::result::Result<Result, ClaimError>
+    where
+        F: FnOnce(Dependency) -> Result,
+    {
+        if !Self::Resources::state_holder().is_initialized() {
+            return Err(ClaimError("resources not initialized"));
+        }
+        let dependency = Self::Resources::with_ref_mut(Claim::<Dependency>::try_claim)?;
+        Ok(f(dependency))
+    }
+}
+
+impl<O, Dependency> WithDependency<Dependency> for O
+where
+    O: Owner<Dependency>,
+{
+    fn with_dependency<Result, F: FnOnce(Dependency) -> Result>(
+        f: F,
+    ) -> core::result::Result<Result, InitError> {
+        Ok(<O as Owner<Dependency>>::try_with_dependency(f)?)
+    }
+}
+
+macro_rules! impl_tuple_dependency {
+    ($first:ident $(, $part:ident)+) => {
+        impl<R, $first $(, $part)+> Claim<($first $(, $part)+)> for R
+        where
+            R: Claim<$first> $(+ Claim<$part>)+,
+        {
+            fn check(&self) -> Result<(), ClaimError> {
+                <R as Claim<$first>>::check(self)?;
+                $(<R as Claim<$part>>::check(self)?;)+
+                Ok(())
+            }
+
+            fn claim(&mut self) -> ($first $(, $part)+) {
+                if let Err(err) = <Self as Claim<($first $(, $part)+)>>::check(self) {
+                    panic!("{}", err);
+                }
+                (
+                    <R as Claim<$first>>::claim(self)
+                    $(, <R as Claim<$part>>::claim(self))+
+                )
+            }
+
+            #[allow(non_snake_case)]
+            fn restore(&mut self, ($first $(, $part)+): ($first $(, $part)+)) {
+                <R as Claim<$first>>::restore(self, $first);
+                $(<R as Claim<$part>>::restore(self, $part);)+
+            }
+        }
+
+        impl<O, $first $(, $part)+> Owner<($first $(, $part)+)> for O
+        where
+            O: Owner<$first> $(+ Owner<$part, Resources = <O as Owner<$first>>::Resources>)+,
+            <<O as Owner<$first>>::Resources as Singleton>::Content:
+                Claim<$first> $(+ Claim<$part>)+,
+        {
+            type Resources = <O as Owner<$first>>::Resources;
+        }
+    };
+}
+
+impl_tuple_dependency!(A, B);
+impl_tuple_dependency!(A, B, C);
+impl_tuple_dependency!(A, B, C, D);
@@ -1,111 +1,431 @@
-use super::Initialized;
-use core::cell::RefCell;
-use core::panic;
-use critical_section::Mutex;
+use super::lock::{CriticalSectionLock, Lock};
+use super::registry::{self, Link, SingletonDescriptor};
+use super::wait_queue::{WaitQueue, Waiter};
+use super::{InitError, TryInitialized};
+use core::cell::{Cell, RefCell};
+use core::error::Error;
+use core::fmt;
+use core::future::Future;
+use core::marker::{PhantomData, PhantomPinned};
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::{Context, Poll};
 
-pub trait SingletonHolder: Initialized + Default + Sync {
+/// Fallible counterpart of `Default` used to create singleton content.
+///
+/// Implemented for all `Default` types. Content that may fail to come up
+/// implements it instead of `Default`.
+pub trait TryDefault: Sized {
+    fn try_default() -> Result<Self, InitError>;
+}
+
+impl<T: Default> TryDefault for T {
+    fn try_default() -> Result<Self, InitError> {
+        Ok(T::default())
+    }
+}
+
+/// Counterpart of `TryDefault` run when a driver is deinitialized.
+///
+/// Content that claimed resources returns them here so that the driver can
+/// be initialized again. Content that owns nothing claimed can rely on the
+/// default, which just drops it.
+pub trait Teardown: Sized {
+    fn teardown(self) {}
+}
+
+/// The singleton is held by an async task through `Singleton::lock()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locked;
+
+impl fmt::Display for Locked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Locked: singleton is held by an async task.")
+    }
+}
+
+impl Error for Locked {}
+
+/// The singleton was not initialized when an async task waited for it, or
+/// was deinitialized while the task waited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotInitialized;
+
+impl fmt::Display for NotInitialized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NotInitialized: singleton is not initialized.")
+    }
+}
+
+impl Error for NotInitialized {}
+
+pub trait SingletonHolder: Default + Sync {
     type Content: 'static;
 
-    fn with<Result, F: FnOnce(Self::Content) -> (Self::Content, Result)>(&self, f: F) -> Result;
+    /// Like `with()`, but reports instead of panicking if the content is
+    /// held by an async task.
+    fn try_with<Result, F>(&self, f: F) -> core::result::Result<Result, Locked>
+    where
+        F: FnOnce(Self::Content) -> (Self::Content, Result);
 
-    fn with_ref<Result, F>(&self, f: F) -> Result
+    /// Removes the content and hands it to `f` for teardown. The holder can
+    /// be initialized again afterwards. Does nothing if not initialized.
+    fn deinit_with<F: FnOnce(Self::Content)>(&self, f: F) -> core::result::Result<(), Locked>;
+
+    /// Panics if the content is held by an async task.
+    fn with<Result, F: FnOnce(Self::Content) -> (Self::Content, Result)>(&self, f: F) -> Result {
+        self.try_with(f).expect("singleton locked")
+    }
+
+    fn try_with_ref<Result, F>(&self, f: F) -> core::result::Result<Result, Locked>
     where
         F: FnOnce(&Self::Content) -> Result,
     {
-        self.with(|state| {
+        self.try_with(|state| {
             let result = f(&state);
             (state, result)
         })
     }
 
-    fn with_ref_mut<Result, F>(&self, f: F) -> Result
+    fn try_with_ref_mut<Result, F>(&self, f: F) -> core::result::Result<Result, Locked>
     where
         F: FnOnce(&mut Self::Content) -> Result,
     {
-        self.with(|mut state| {
+        self.try_with(|mut state| {
             let result = f(&mut state);
             (state, result)
         })
     }
+
+    fn with_ref<Result, F>(&self, f: F) -> Result
+    where
+        F: FnOnce(&Self::Content) -> Result,
+    {
+        self.try_with_ref(f).expect("singleton locked")
+    }
+
+    fn with_ref_mut<Result, F>(&self, f: F) -> Result
+    where
+        F: FnOnce(&mut Self::Content) -> Result,
+    {
+        self.try_with_ref_mut(f).expect("singleton locked")
+    }
 }
 
-pub struct SingletonHolderImpl<Content>
+pub struct SingletonHolderImpl<Content, L = CriticalSectionLock>
 where
     Content: 'static + Send,
 {
-    state: Mutex<RefCell<Option<Content>>>,
+    state: RefCell<Option<Content>>,
+    /// Set while a `SingletonGuard` exists. The state is not touched then,
+    /// the guard has exclusive access to the content.
+    locked: Cell<bool>,
+    waiters: RefCell<WaitQueue>,
+    link: Link<SingletonDescriptor>,
+    lock: PhantomData<L>,
 }
 
-impl<Content> SingletonHolderImpl<Content>
+// SAFETY: The state is only accessed while holding the lock and the content
+// may be sent to whichever context holds it.
+unsafe impl<Content, L> Sync for SingletonHolderImpl<Content, L>
 where
     Content: Send,
+    L: Lock,
+{
+}
+
+impl<Content, L> SingletonHolderImpl<Content, L>
+where
+    Content: Send,
+    L: Lock,
 {
     pub const fn new() -> Self {
         SingletonHolderImpl {
-            state: Mutex::new(RefCell::new(None)),
+            state: RefCell::new(None),
+            locked: Cell::new(false),
+            waiters: RefCell::new(WaitQueue::new()),
+            link: Link::new(),
+            lock: PhantomData,
         }
     }
 
-    pub fn reset(&mut self) {
-        critical_section::with(|cs| {
-            let mut content = self.state.borrow_ref_mut(cs);
-            *content = None;
-        });
+    /// Locks the content across await points. See `Singleton::lock()`.
+    pub fn lock(&self) -> LockFuture<'_, Content, L> {
+        LockFuture {
+            holder: self,
+            waiter: Waiter::new(),
+            _pinned: PhantomPinned,
+        }
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        // Only initialized content can be locked.
+        L::lock(|| self.locked.get() || self.state.borrow().is_some())
+    }
+
+    /// Initializes the holder with the content created by `f`.
+    pub fn try_init_with<F>(&self, f: F) -> Result<(), InitError>
+    where
+        F: FnOnce() -> Result<Content, InitError>,
+    {
+        L::lock(|| {
+            if self.locked.get() {
+                return Err(InitError::AlreadyInitialized);
+            }
+            let mut prev = self.state.borrow_mut();
+            if prev.is_none() {
+                prev.replace(f()?);
+                Ok(())
+            } else {
+                Err(InitError::AlreadyInitialized)
+            }
+        })
+    }
+
+    /// Whether an async task holds the content through `lock()`.
+    pub fn is_locked(&self) -> bool {
+        L::lock(|| self.locked.get())
+    }
+
+    #[cfg(any(test, feature = "std"))]
+    fn replace(&self, content: Option<Content>) -> Option<Content> {
+        L::lock(|| {
+            assert!(!self.locked.get(), "singleton locked");
+            self.state.replace(content)
+        })
     }
 }
 
-impl<Content> Default for SingletonHolderImpl<Content>
+impl<Content, L> Default for SingletonHolderImpl<Content, L>
 where
     Content: Send,
+    L: Lock,
 {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<Content> Initialized for SingletonHolderImpl<Content>
+impl<Content, L> TryInitialized for SingletonHolderImpl<Content, L>
 where
-    Content: Default + Send,
+    Content: TryDefault + Send,
+    L: Lock,
 {
-    fn init(&self) {
-        critical_section::with(|cs| {
-            let mut prev = self.state.borrow_ref_mut(cs);
-            if prev.is_none() {
-                prev.replace(Default::default());
-            } else {
-                panic!("already initialized");
-            }
-        });
+    fn try_init(&self) -> Result<(), InitError> {
+        self.try_init_with(Content::try_default)
     }
 
     fn is_initialized(&self) -> bool {
-        critical_section::with(|cs| self.state.borrow_ref(cs).is_some())
+        SingletonHolderImpl::is_initialized(self)
     }
 }
 
-impl<Content> SingletonHolder for SingletonHolderImpl<Content>
+impl<Content, L> SingletonHolder for SingletonHolderImpl<Content, L>
 where
-    Content: Default + Send,
+    Content: Send,
+    L: Lock,
 {
     type Content = Content;
 
-    fn with<Result, F: FnOnce(Content) -> (Content, Result)>(&self, f: F) -> Result {
-        critical_section::with(|cs| {
-            let mut opt = self.state.borrow_ref_mut(cs);
+    fn try_with<Result, F>(&self, f: F) -> core::result::Result<Result, Locked>
+    where
+        F: FnOnce(Content) -> (Content, Result),
+    {
+        L::lock(|| {
+            if self.locked.get() {
+                return Err(Locked);
+            }
+            let mut opt = self
+                .state
+                .try_borrow_mut()
+                .expect("singleton already in use");
             let prev = opt.take().expect("singleton not initialized");
             let (after, result) = f(prev);
             opt.replace(after);
-            result
+            Ok(result)
         })
     }
+
+    fn deinit_with<F: FnOnce(Content)>(&self, f: F) -> core::result::Result<(), Locked> {
+        L::lock(|| {
+            if self.locked.get() {
+                return Err(Locked);
+            }
+            let content = self
+                .state
+                .try_borrow_mut()
+                .expect("singleton already in use")
+                .take();
+            if let Some(content) = content {
+                f(content);
+                // Waiting tasks learn that the content is gone.
+                self.waiters.borrow().wake_head();
+            }
+            Ok(())
+        })
+    }
+}
+
+pub struct LockFuture<'a, Content, L>
+where
+    Content: 'static + Send,
+    L: Lock,
+{
+    holder: &'a SingletonHolderImpl<Content, L>,
+    waiter: Waiter,
+    _pinned: PhantomPinned,
+}
+
+impl<'a, Content, L> Future for LockFuture<'a, Content, L>
+where
+    Content: Send,
+    L: Lock,
+{
+    type Output = Result<SingletonGuard<'a, Content, L>, NotInitialized>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: The waiter is never moved out of the pinned future.
+        let this = unsafe { self.get_unchecked_mut() };
+        let holder = this.holder;
+        let mut waiter = NonNull::from(&mut this.waiter);
+        L::lock(|| {
+            let mut waiters = holder.waiters.borrow_mut();
+            // SAFETY: The waiter is only accessed while holding the lock.
+            let queued = unsafe { waiter.as_ref() }.is_queued();
+            // Locked content is initialized.
+            let uninitialized = !holder.locked.get()
+                && holder.state.try_borrow().is_ok_and(|state| state.is_none());
+            if uninitialized {
+                if queued {
+                    let was_head = waiters.is_head(waiter);
+                    // SAFETY: The waiter is queued.
+                    unsafe { waiters.remove(waiter) };
+                    if was_head {
+                        // The next task learns about it, too.
+                        waiters.wake_head();
+                    }
+                }
+                return Poll::Ready(Err(NotInitialized));
+            }
+            let turn = if queued {
+                waiters.is_head(waiter)
+            } else {
+                waiters.is_empty()
+            };
+            if turn && !holder.locked.get() {
+                if let Ok(mut state) = holder.state.try_borrow_mut() {
+                    if let Some(content) = state.as_mut() {
+                        if queued {
+                            // SAFETY: The waiter is queued.
+                            unsafe { waiters.remove(waiter) };
+                        }
+                        let content = NonNull::from(content);
+                        holder.locked.set(true);
+                        return Poll::Ready(Ok(SingletonGuard { holder, content }));
+                    }
+                }
+            }
+            // SAFETY: See above.
+            unsafe { waiter.as_mut() }.register(cx.waker());
+            if !queued {
+                // SAFETY: The future is pinned and removes the waiter on drop.
+                unsafe { waiters.push(waiter) };
+            }
+            Poll::Pending
+        })
+    }
+}
+
+impl<Content, L> Drop for LockFuture<'_, Content, L>
+where
+    Content: 'static + Send,
+    L: Lock,
+{
+    fn drop(&mut self) {
+        let waiter = NonNull::from(&mut self.waiter);
+        L::lock(|| {
+            // SAFETY: The waiter is only accessed while holding the lock.
+            if !unsafe { waiter.as_ref() }.is_queued() {
+                return;
+            }
+            let mut waiters = self.holder.waiters.borrow_mut();
+            let was_head = waiters.is_head(waiter);
+            // SAFETY: The waiter is queued.
+            unsafe { waiters.remove(waiter) };
+            if was_head {
+                // The cancelled task may already have been woken.
+                waiters.wake_head();
+            }
+        });
+    }
+}
+
+/// Exclusive access to singleton content, released on drop.
+pub struct SingletonGuard<'a, Content, L>
+where
+    Content: 'static + Send,
+    L: Lock,
+{
+    holder: &'a SingletonHolderImpl<Content, L>,
+    content: NonNull<Content>,
+}
+
+impl<Content, L> Deref for SingletonGuard<'_, Content, L>
+where
+    Content: Send,
+    L: Lock,
+{
+    type Target = Content;
+
+    fn deref(&self) -> &Content {
+        // SAFETY: The holder is locked, nothing else accesses the content.
+        unsafe { self.content.as_ref() }
+    }
+}
+
+impl<Content, L> DerefMut for SingletonGuard<'_, Content, L>
+where
+    Content: Send,
+    L: Lock,
+{
+    fn deref_mut(&mut self) -> &mut Content {
+        // SAFETY: See `deref()`.
+        unsafe { self.content.as_mut() }
+    }
+}
+
+impl<Content, L> Drop for SingletonGuard<'_, Content, L>
+where
+    Content: 'static + Send,
+    L: Lock,
+{
+    fn drop(&mut self) {
+        L::lock(|| {
+            self.holder.locked.set(false);
+            self.holder.waiters.borrow().wake_head();
+        });
+    }
 }
 
 pub trait Singleton: Sized + Sync {
-    type Content: 'static + Default + Send;
+    type Content: 'static + Send;
+    type Lock: Lock + 'static;
+
+    const DESCRIPTOR: SingletonDescriptor;
+
+    fn state_holder() -> &'static SingletonHolderImpl<Self::Content, Self::Lock>;
+
+    /// Creates the content when the singleton is initialized. Derived
+    /// singletons use `TryDefault`.
+    fn try_new() -> Result<Self::Content, InitError>;
 
     fn with_state_holder<Result, F>(f: F) -> Result
     where
-        F: FnOnce(&SingletonHolderImpl<Self::Content>) -> Result;
+        F: FnOnce(&SingletonHolderImpl<Self::Content, Self::Lock>) -> Result,
+    {
+        f(Self::state_holder())
+    }
 
     fn with<Result, F>(f: F) -> Result
     where
@@ -127,20 +447,446 @@ pub trait Singleton: Sized + Sync {
     {
         Self::with_state_holder(|state_holder| state_holder.with_ref_mut(f))
     }
+
+    fn try_with_ref<Result, F>(f: F) -> core::result::Result<Result, Locked>
+    where
+        F: FnOnce(&Self::Content) -> Result,
+    {
+        Self::with_state_holder(|state_holder| state_holder.try_with_ref(f))
+    }
+
+    fn try_with_ref_mut<Result, F>(f: F) -> core::result::Result<Result, Locked>
+    where
+        F: FnOnce(&mut Self::Content) -> Result,
+    {
+        Self::with_state_holder(|state_holder| state_holder.try_with_ref_mut(f))
+    }
+
+    fn deinit_with<F>(f: F) -> core::result::Result<(), Locked>
+    where
+        F: FnOnce(Self::Content),
+    {
+        Self::with_state_holder(|state_holder| state_holder.deinit_with(f))
+    }
+
+    fn deinit() -> core::result::Result<(), Locked> {
+        Self::deinit_with(drop)
+    }
+
+    /// Lists the singleton in the registry. Called when it is initialized.
+    fn register() {
+        let state_holder = Self::state_holder();
+        registry::SINGLETONS.push(&state_holder.link, const { &Self::DESCRIPTOR });
+    }
+
+    /// Locks the singleton so that an async task can hold it across await
+    /// points. Contending tasks are served in FIFO order. While it is
+    /// locked, the singleton still reports being initialized, `try_with_*`
+    /// and `deinit()` return `Locked` and `with_*` panics. Waiting fails with
+    /// `NotInitialized` if the singleton is not initialized or is
+    /// deinitialized meanwhile.
+    fn lock() -> LockFuture<'static, Self::Content, Self::Lock> {
+        Self::state_holder().lock()
+    }
+
+    /// Installs `content` as a test double for the duration of `f` and
+    /// restores the previous content afterwards, even if `f` panics.
+    ///
+    /// Substitutions of all singletons are serialized across threads, so
+    /// tests that substitute the singletons they use do not observe each
+    /// other's doubles. Tests that use a singleton without substituting it
+    /// are not serialized and may see a double. A thread may nest
+    /// substitutions.
+    #[cfg(any(test, feature = "std"))]
+    fn substitute<Result, F>(content: Self::Content, f: F) -> Result
+    where
+        F: FnOnce() -> Result,
+    {
+        struct Restore<S: Singleton>(Option<S::Content>);
+
+        impl<S: Singleton> Drop for Restore<S> {
+            fn drop(&mut self) {
+                S::with_state_holder(|state_holder| state_holder.replace(self.0.take()));
+            }
+        }
+
+        let _lock = SUBSTITUTION.acquire();
+        let _restore = Restore::<Self>(Self::with_state_holder(|state_holder| {
+            state_holder.replace(Some(content))
+        }));
+        f()
+    }
+}
+
+/// Re-entrant lock serializing substitutions across threads.
+#[cfg(any(test, feature = "std"))]
+struct SubstitutionLock {
+    /// Thread holding the lock and how often it acquired it.
+    owner: std::sync::Mutex<(Option<std::thread::ThreadId>, usize)>,
+    released: std::sync::Condvar,
+}
+
+#[cfg(any(test, feature = "std"))]
+static SUBSTITUTION: SubstitutionLock = SubstitutionLock {
+    owner: std::sync::Mutex::new((None, 0)),
+    released: std::sync::Condvar::new(),
+};
+
+#[cfg(any(test, feature = "std"))]
+impl SubstitutionLock {
+    fn owner(&self) -> std::sync::MutexGuard<'_, (Option<std::thread::ThreadId>, usize)> {
+        self.owner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn acquire(&'static self) -> SubstitutionGuard {
+        let current = std::thread::current().id();
+        let mut owner = self.owner();
+        while owner.0.is_some_and(|thread| thread != current) {
+            owner = self
+                .released
+                .wait(owner)
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+        }
+        *owner = (Some(current), owner.1 + 1);
+        SubstitutionGuard(self)
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+struct SubstitutionGuard(&'static SubstitutionLock);
+
+#[cfg(any(test, feature = "std"))]
+impl Drop for SubstitutionGuard {
+    fn drop(&mut self) {
+        let mut owner = self.0.owner();
+        owner.1 -= 1;
+        if owner.1 == 0 {
+            owner.0 = None;
+            self.0.released.notify_one();
+        }
+    }
 }
 
-impl<S, Content> Initialized for S
+/// A singleton type with a fixed number of instances. Every instance is
+/// initialized and locked on its own and is accessed as `Instance<S, INDEX>`.
+pub trait Instances: Sized + Sync + 'static {
+    type Content: 'static + Send;
+    type Lock: Lock + 'static;
+
+    const NAME: &'static str;
+    const COUNT: usize;
+
+    fn state_holders() -> &'static [SingletonHolderImpl<Self::Content, Self::Lock>];
+
+    /// Creates the content of instance `index`, e.g. to pick its
+    /// peripherals. Derived singletons use `TryDefault` unless they name a
+    /// constructor with `new = ...`.
+    fn try_new(index: usize) -> Result<Self::Content, InitError>;
+}
+
+/// Instance `INDEX` of a multi-instance singleton.
+pub struct Instance<S, const INDEX: usize>(PhantomData<S>);
+
+impl<S, const INDEX: usize> Instance<S, INDEX>
 where
-    Content: Default + Send,
+    S: Instances,
+{
+    pub const fn new() -> Self {
+        Instance(PhantomData)
+    }
+}
+
+impl<S, const INDEX: usize> Default for Instance<S, INDEX>
+where
+    S: Instances,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S, const INDEX: usize> Singleton for Instance<S, INDEX>
+where
+    S: Instances,
+{
+    type Content = S::Content;
+    type Lock = S::Lock;
+
+    const DESCRIPTOR: SingletonDescriptor =
+        SingletonDescriptor::new_instance(S::NAME, INDEX, || Self::new().is_initialized());
+
+    fn state_holder() -> &'static SingletonHolderImpl<Self::Content, Self::Lock> {
+        const { assert!(INDEX < S::COUNT, "singleton instance out of range") };
+        &S::state_holders()[INDEX]
+    }
+
+    fn try_new() -> Result<Self::Content, InitError> {
+        S::try_new(INDEX)
+    }
+}
+
+impl<S, Content> TryInitialized for S
+where
+    Content: Send,
     S: Singleton<Content = Content>,
 {
-    fn init(&self) {
-        Self::with_state_holder(|state_holder| {
-            state_holder.ensure_is_initialized();
-        });
+    fn try_init(&self) -> Result<(), InitError> {
+        Self::with_state_holder(|state_holder| state_holder.try_init_with(Self::try_new))?;
+        Self::register();
+        Ok(())
     }
 
     fn is_initialized(&self) -> bool {
         Self::with_state_holder(|state_holder| state_holder.is_initialized())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+
+    #[derive(Default)]
+    struct WakeCount(AtomicUsize);
+
+    impl Wake for WakeCount {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn poll<F: Future>(future: Pin<&mut F>, wakes: &Arc<WakeCount>) -> Poll<F::Output> {
+        let waker = Waker::from(wakes.clone());
+        future.poll(&mut Context::from_waker(&waker))
+    }
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Counter(usize);
+
+    macro_rules! test_singleton {
+        ($singleton:ident) => {
+            struct $singleton;
+
+            impl Singleton for $singleton {
+                type Content = Counter;
+                type Lock = CriticalSectionLock;
+
+                const DESCRIPTOR: SingletonDescriptor =
+                    SingletonDescriptor::new(stringify!($singleton), || {
+                        $singleton.is_initialized()
+                    });
+
+                fn state_holder() -> &'static SingletonHolderImpl<Counter> {
+                    static STATE_HOLDER: SingletonHolderImpl<Counter> = SingletonHolderImpl::new();
+                    &STATE_HOLDER
+                }
+
+                fn try_new() -> Result<Counter, InitError> {
+                    Ok(Counter::default())
+                }
+            }
+        };
+    }
+
+    test_singleton!(TestSingleton);
+
+    #[test]
+    fn test_substitute() {
+        TestSingleton.try_init().unwrap();
+        TestSingleton::with_ref_mut(|counter| counter.0 = 1);
+
+        let seen = TestSingleton::substitute(Counter(42), || {
+            TestSingleton::with_ref_mut(|counter| counter.0 += 1);
+            TestSingleton::with_ref(|counter| counter.0)
+        });
+        assert_eq!(seen, 43);
+        TestSingleton::with_ref(|counter| assert_eq!(*counter, Counter(1)));
+
+        let result = std::panic::catch_unwind(|| {
+            TestSingleton::substitute(Counter(7), || panic!("test failure"))
+        });
+        assert!(result.is_err());
+        TestSingleton::with_ref(|counter| assert_eq!(*counter, Counter(1)));
+
+        TestSingleton::deinit().unwrap();
+        assert!(!TestSingleton.is_initialized());
+        TestSingleton::substitute(Counter(3), || {
+            assert!(TestSingleton.is_initialized());
+        });
+        assert!(!TestSingleton.is_initialized());
+    }
+
+    #[test]
+    fn test_try_init_twice() {
+        test_singleton!(Twice);
+
+        Twice.try_init().unwrap();
+        Twice::with_ref_mut(|counter| counter.0 = 1);
+        assert_eq!(Twice.try_init(), Err(InitError::AlreadyInitialized));
+        Twice::with_ref(|counter| assert_eq!(*counter, Counter(1)));
+        assert_eq!(Twice.try_ensure_is_initialized(), Ok(()));
+
+        Twice::deinit().unwrap();
+        Twice.try_init().unwrap();
+        Twice::with_ref(|counter| assert_eq!(*counter, Counter(0)));
+    }
+
+    #[test]
+    fn test_nested_substitute() {
+        test_singleton!(Outer);
+        test_singleton!(Inner);
+
+        let seen = Outer::substitute(Counter(1), || {
+            Inner::substitute(Counter(2), || {
+                Outer::substitute(Counter(3), || {
+                    Outer::with_ref(|counter| counter.0) + Inner::with_ref(|counter| counter.0)
+                })
+            })
+        });
+        assert_eq!(seen, 5);
+        assert!(!Outer.is_initialized() && !Inner.is_initialized());
+    }
+
+    #[test]
+    fn test_lock_fifo() {
+        let holder = SingletonHolderImpl::<Counter>::new();
+        holder.try_init().unwrap();
+        let (wakes_b, wakes_c) = (Arc::default(), Arc::default());
+
+        let mut a = std::pin::pin!(holder.lock());
+        let Poll::Ready(Ok(mut guard_a)) = poll(a.as_mut(), &Arc::default()) else {
+            panic!("uncontended lock must be ready");
+        };
+        guard_a.0 += 1;
+
+        let mut b = std::pin::pin!(holder.lock());
+        let mut c = std::pin::pin!(holder.lock());
+        assert!(poll(b.as_mut(), &wakes_b).is_pending());
+        assert!(poll(c.as_mut(), &wakes_c).is_pending());
+
+        drop(guard_a);
+        assert_eq!(wakes_b.0.load(Ordering::Relaxed), 1);
+        assert_eq!(wakes_c.0.load(Ordering::Relaxed), 0);
+
+        // Tasks queued first are served first.
+        assert!(poll(c.as_mut(), &wakes_c).is_pending());
+        let Poll::Ready(Ok(mut guard_b)) = poll(b.as_mut(), &wakes_b) else {
+            panic!("head of the queue must be served");
+        };
+        guard_b.0 += 1;
+        drop(guard_b);
+        assert_eq!(wakes_c.0.load(Ordering::Relaxed), 1);
+
+        let Poll::Ready(Ok(guard_c)) = poll(c.as_mut(), &wakes_c) else {
+            panic!("head of the queue must be served");
+        };
+        assert_eq!(*guard_c, Counter(2));
+    }
+
+    #[test]
+    fn test_lock_cancelled_waiter() {
+        let holder = SingletonHolderImpl::<Counter>::new();
+        holder.try_init().unwrap();
+        let (wakes_b, wakes_c) = (Arc::default(), Arc::default());
+
+        let mut a = std::pin::pin!(holder.lock());
+        let Poll::Ready(Ok(guard_a)) = poll(a.as_mut(), &Arc::default()) else {
+            panic!("uncontended lock must be ready");
+        };
+        let mut b = Box::pin(holder.lock());
+        let mut c = std::pin::pin!(holder.lock());
+        assert!(poll(b.as_mut(), &wakes_b).is_pending());
+        assert!(poll(c.as_mut(), &wakes_c).is_pending());
+
+        // Synchronous access is reported while locked.
+        assert_eq!(holder.try_with_ref(|_| ()), Err(Locked));
+        assert_eq!(holder.deinit_with(drop), Err(Locked));
+        assert!(holder.is_initialized());
+        assert_eq!(holder.try_init(), Err(InitError::AlreadyInitialized));
+
+        drop(guard_a);
+        assert_eq!(wakes_b.0.load(Ordering::Relaxed), 1);
+
+        // A woken waiter that gives up hands the lock on.
+        drop(b);
+        assert_eq!(wakes_c.0.load(Ordering::Relaxed), 1);
+        assert!(poll(c.as_mut(), &wakes_c).is_ready());
+    }
+
+    #[test]
+    fn test_lock_not_initialized() {
+        let holder = SingletonHolderImpl::<Counter>::new();
+        let wakes_b = Arc::default();
+
+        let mut a = std::pin::pin!(holder.lock());
+        assert_eq!(
+            poll(a.as_mut(), &Arc::default()).map(|guard| guard.err()),
+            Poll::Ready(Some(NotInitialized))
+        );
+
+        holder.try_init().unwrap();
+        let mut a = std::pin::pin!(holder.lock());
+        let Poll::Ready(Ok(guard_a)) = poll(a.as_mut(), &Arc::default()) else {
+            panic!("uncontended lock must be ready");
+        };
+        let mut b = std::pin::pin!(holder.lock());
+        assert!(poll(b.as_mut(), &wakes_b).is_pending());
+
+        // Deinitialized while the task waits.
+        drop(guard_a);
+        holder.deinit_with(drop).unwrap();
+        assert_eq!(wakes_b.0.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            poll(b.as_mut(), &wakes_b).map(|guard| guard.err()),
+            Poll::Ready(Some(NotInitialized))
+        );
+    }
+
+    struct TestInstances;
+
+    impl Instances for TestInstances {
+        type Content = Counter;
+        type Lock = CriticalSectionLock;
+
+        const NAME: &'static str = "TestInstances";
+        const COUNT: usize = 2;
+
+        fn state_holders() -> &'static [SingletonHolderImpl<Counter>] {
+            static STATE_HOLDERS: [SingletonHolderImpl<Counter>; 2] =
+                [const { SingletonHolderImpl::new() }; 2];
+            &STATE_HOLDERS
+        }
+
+        fn try_new(index: usize) -> Result<Counter, InitError> {
+            Ok(Counter(index * 10))
+        }
+    }
+
+    #[test]
+    fn test_instances() {
+        type First = Instance<TestInstances, 0>;
+        type Second = Instance<TestInstances, 1>;
+
+        First::new().try_init().unwrap();
+        assert!(!Second::new().is_initialized());
+        Second::new().try_init().unwrap();
+        Second::with_ref(|counter| assert_eq!(*counter, Counter(10)));
+
+        First::with_ref_mut(|counter| counter.0 = 1);
+        Second::with_ref_mut(|counter| counter.0 = 2);
+        First::with_ref(|counter| assert_eq!(*counter, Counter(1)));
+
+        First::deinit().unwrap();
+        assert!(!First::new().is_initialized());
+        Second::with_ref(|counter| assert_eq!(*counter, Counter(2)));
+        assert_eq!(Second::DESCRIPTOR.index(), Some(1));
+    }
+}
@@ -1,11 +1,12 @@
+use super::registry::{self, Link};
 use core::{
-    fmt,
+    any, fmt,
     mem::MaybeUninit,
     ops, ptr,
     sync::atomic::{self, AtomicUsize},
 };
 
-pub struct SharedToken<'a, Token: Release + Send + Default> {
+pub struct SharedToken<'a, Token: Release + Counted + Send + Default> {
     token: Token,
     count: &'a AtomicUsize,
 }
@@ -14,14 +15,75 @@ pub trait Release {
     fn release(&self);
 }
 
-impl<'a, Token: Release + Send + Default> SharedToken<'a, Token> {
+/// Gives a token type its own holder counter. Implemented with
+/// `di::holders!()` rather than by hand so that no two token types can end
+/// up sharing a counter.
+pub trait Counted {
+    fn holders() -> &'static Holders;
+}
+
+/// Implements `Counted` for the given token types, each with a counter of
+/// its own.
+///
+/// ```ignore
+/// di::holders!(NrfSleepOscToken, NrfHighAccOscToken);
+/// ```
+#[macro_export]
+macro_rules! holders {
+    ($($token:ty),+ $(,)?) => {
+        $(
+            impl $crate::token::Counted for $token {
+                fn holders() -> &'static $crate::token::Holders {
+                    static HOLDERS: $crate::token::Holders =
+                        $crate::token::Holders::new::<$token>();
+                    &HOLDERS
+                }
+            }
+        )+
+    };
+}
+
+/// Holder counter of a token type, also listed by the registry.
+pub struct Holders {
+    count: AtomicUsize,
+    name: fn() -> &'static str,
+    link: Link<Holders>,
+}
+
+impl Holders {
+    pub const fn new<Token>() -> Self {
+        Holders {
+            count: AtomicUsize::new(0),
+            name: any::type_name::<Token>,
+            link: Link::new(),
+        }
+    }
+
+    /// Type name of the token.
+    pub fn name(&self) -> &'static str {
+        (self.name)()
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(atomic::Ordering::Relaxed)
+    }
+}
+
+impl<'a, Token: Release + Counted + Send + Default> SharedToken<'a, Token> {
     pub fn new(token: Token) -> Self {
-        static COUNT: AtomicUsize = AtomicUsize::new(0);
         const { assert!(size_of::<Token>() == 0) }
-        Self {
+        let holders = Token::holders();
+        registry::TOKENS.push(&holders.link, holders);
+        let shared_token = Self {
             token,
-            count: &COUNT,
-        }
+            count: &holders.count,
+        };
+        shared_token.increment_uses();
+        shared_token
+    }
+
+    pub fn holders() -> usize {
+        Token::holders().count()
     }
 
     pub fn acquire(&self) -> Token {
@@ -35,9 +97,11 @@ impl<'a, Token: Release + Send + Default> SharedToken<'a, Token> {
         }
     }
 
-    pub fn release(&self, _token: Token) {
-        if self.decrement_uses() == 0 {
-            panic!("released too many tokens")
+    pub fn release(&self, token: Token) {
+        match self.decrement_uses() {
+            0 => panic!("released too many tokens"),
+            1 => token.release(),
+            _ => {}
         }
     }
 
@@ -49,13 +113,19 @@ impl<'a, Token: Release + Send + Default> SharedToken<'a, Token> {
     }
 
     fn decrement_uses(&self) -> usize {
-        self.count.fetch_sub(1, atomic::Ordering::Relaxed)
+        self.count
+            .fetch_update(
+                atomic::Ordering::Relaxed,
+                atomic::Ordering::Relaxed,
+                |count| count.checked_sub(1),
+            )
+            .unwrap_or(0)
     }
 }
 
 const MAX_TOKENS: usize = isize::MAX as usize;
 
-impl<'a, Token: Release + Send + Default> Clone for SharedToken<'a, Token> {
+impl<'a, Token: Release + Counted + Send + Default> Clone for SharedToken<'a, Token> {
     fn clone(&self) -> Self {
         Self {
             token: self.acquire(),
@@ -64,15 +134,15 @@ impl<'a, Token: Release + Send + Default> Clone for SharedToken<'a, Token> {
     }
 }
 
-impl<Token: Release + Send + Default> Drop for SharedToken<'_, Token> {
+impl<Token: Release + Counted + Send + Default> Drop for SharedToken<'_, Token> {
     fn drop(&mut self) {
-        if self.decrement_uses() == 0 {
+        if self.decrement_uses() == 1 {
             self.token.release();
         }
     }
 }
 
-impl<Token: Release + Send + Default> fmt::Debug for SharedToken<'_, Token>
+impl<Token: Release + Counted + Send + Default> fmt::Debug for SharedToken<'_, Token>
 where
     Token: fmt::Debug,
 {
@@ -81,7 +151,7 @@ where
     }
 }
 
-impl<Token: Release + Send + Default> fmt::Display for SharedToken<'_, Token>
+impl<Token: Release + Counted + Send + Default> fmt::Display for SharedToken<'_, Token>
 where
     Token: fmt::Display,
 {
@@ -90,7 +160,7 @@ where
     }
 }
 
-impl<Token: Release + Send + Default> ops::Deref for SharedToken<'_, Token> {
+impl<Token: Release + Counted + Send + Default> ops::Deref for SharedToken<'_, Token> {
     type Target = Token;
 
     fn deref(&self) -> &Self::Target {
@@ -102,18 +172,119 @@ impl<Token: Release + Send + Default> ops::Deref for SharedToken<'_, Token> {
 mod test {
     use super::*;
 
-    struct TestToken;
+    macro_rules! test_token {
+        ($token:ident) => {
+            #[derive(Default, Debug)]
+            struct $token;
 
-    #[test]
-    fn test_token() {
-        let mut was_released = false;
+            impl $token {
+                fn releases() -> &'static AtomicUsize {
+                    static RELEASES: AtomicUsize = AtomicUsize::new(0);
+                    &RELEASES
+                }
+            }
 
-        impl Release<Self> for TestToken {
-            fn release(token: &Self) {
-                was_released = true;
+            impl Release for $token {
+                fn release(&self) {
+                    Self::releases().fetch_add(1, atomic::Ordering::Relaxed);
+                }
             }
-        }
 
-        let shared_token = SharedToken::new(Default::default());
+            crate::holders!($token);
+        };
+    }
+
+    #[test]
+    fn test_new_and_drop() {
+        test_token!(TestToken);
+
+        let shared_token = SharedToken::new(TestToken);
+        assert_eq!(SharedToken::<TestToken>::holders(), 1);
+
+        drop(shared_token);
+        assert_eq!(SharedToken::<TestToken>::holders(), 0);
+        assert_eq!(TestToken::releases().load(atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_clone() {
+        test_token!(TestToken);
+
+        let shared_token = SharedToken::new(TestToken);
+        let cloned_token = shared_token.clone();
+        assert_eq!(SharedToken::<TestToken>::holders(), 2);
+
+        drop(shared_token);
+        assert_eq!(SharedToken::<TestToken>::holders(), 1);
+        assert_eq!(TestToken::releases().load(atomic::Ordering::Relaxed), 0);
+
+        drop(cloned_token);
+        assert_eq!(SharedToken::<TestToken>::holders(), 0);
+        assert_eq!(TestToken::releases().load(atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_acquire_and_release() {
+        test_token!(TestToken);
+
+        let shared_token = SharedToken::new(TestToken);
+        let token = shared_token.acquire();
+        assert_eq!(SharedToken::<TestToken>::holders(), 2);
+
+        shared_token.release(token);
+        assert_eq!(SharedToken::<TestToken>::holders(), 1);
+        assert_eq!(TestToken::releases().load(atomic::Ordering::Relaxed), 0);
+
+        drop(shared_token);
+        assert_eq!(TestToken::releases().load(atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_acquired_token_outlives_shared_token() {
+        test_token!(TestToken);
+
+        let shared_token = SharedToken::new(TestToken);
+        let token = shared_token.acquire();
+        drop(shared_token);
+        assert_eq!(SharedToken::<TestToken>::holders(), 1);
+        assert_eq!(TestToken::releases().load(atomic::Ordering::Relaxed), 0);
+
+        let shared_token = SharedToken::new(TestToken);
+        shared_token.release(token);
+        drop(shared_token);
+        assert_eq!(SharedToken::<TestToken>::holders(), 0);
+        assert_eq!(TestToken::releases().load(atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "released too many tokens")]
+    fn test_release_too_many() {
+        test_token!(TestToken);
+
+        let shared_token = SharedToken::new(TestToken);
+        let token = shared_token.acquire();
+        shared_token.release(token);
+        // Gives up the share held by the shared token itself.
+        shared_token.release(TestToken);
+        shared_token.release(TestToken);
+    }
+
+    #[test]
+    fn test_holders_per_token_type() {
+        test_token!(TestToken);
+        test_token!(OtherTestToken);
+
+        let shared_token = SharedToken::new(TestToken);
+        let other_shared_token = SharedToken::new(OtherTestToken);
+        let _other_token = other_shared_token.acquire();
+        assert_eq!(SharedToken::<TestToken>::holders(), 1);
+        assert_eq!(SharedToken::<OtherTestToken>::holders(), 2);
+
+        drop(shared_token);
+        assert_eq!(TestToken::releases().load(atomic::Ordering::Relaxed), 1);
+        assert_eq!(
+            OtherTestToken::releases().load(atomic::Ordering::Relaxed),
+            0
+        );
     }
 }
@@ -0,0 +1,103 @@
+use core::ptr::NonNull;
+use core::task::Waker;
+
+/// Intrusive FIFO of tasks waiting for a singleton. The nodes live in the
+/// pinned futures of the waiting tasks, so no storage has to be reserved
+/// up front. All methods must be called while holding the singleton's lock.
+pub(crate) struct WaitQueue {
+    head: Option<NonNull<Waiter>>,
+    tail: Option<NonNull<Waiter>>,
+}
+
+pub(crate) struct Waiter {
+    waker: Option<Waker>,
+    next: Option<NonNull<Waiter>>,
+    queued: bool,
+}
+
+impl Waiter {
+    pub(crate) const fn new() -> Self {
+        Waiter {
+            waker: None,
+            next: None,
+            queued: false,
+        }
+    }
+
+    pub(crate) fn is_queued(&self) -> bool {
+        self.queued
+    }
+
+    pub(crate) fn register(&mut self, waker: &Waker) {
+        match &mut self.waker {
+            Some(prev) => prev.clone_from(waker),
+            None => self.waker = Some(waker.clone()),
+        }
+    }
+}
+
+impl WaitQueue {
+    pub(crate) const fn new() -> Self {
+        WaitQueue {
+            head: None,
+            tail: None,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    pub(crate) fn is_head(&self, waiter: NonNull<Waiter>) -> bool {
+        self.head == Some(waiter)
+    }
+
+    /// # Safety
+    ///
+    /// The waiter must not be queued yet and must stay pinned until removed.
+    pub(crate) unsafe fn push(&mut self, mut waiter: NonNull<Waiter>) {
+        let node = waiter.as_mut();
+        node.next = None;
+        node.queued = true;
+        match self.tail {
+            Some(mut tail) => tail.as_mut().next = Some(waiter),
+            None => self.head = Some(waiter),
+        }
+        self.tail = Some(waiter);
+    }
+
+    /// # Safety
+    ///
+    /// The waiter must be queued.
+    pub(crate) unsafe fn remove(&mut self, mut waiter: NonNull<Waiter>) {
+        let next = waiter.as_ref().next;
+        let mut prev = None;
+        let mut current = self.head;
+        while let Some(node) = current {
+            if node == waiter {
+                break;
+            }
+            prev = Some(node);
+            current = node.as_ref().next;
+        }
+        match prev {
+            Some(mut prev) => prev.as_mut().next = next,
+            None => self.head = next,
+        }
+        if self.tail == Some(waiter) {
+            self.tail = prev;
+        }
+        let node = waiter.as_mut();
+        node.next = None;
+        node.queued = false;
+    }
+
+    pub(crate) fn wake_head(&self) {
+        if let Some(head) = self.head {
+            // SAFETY: Queued waiters stay pinned until they are removed.
+            if let Some(waker) = &unsafe { head.as_ref() }.waker {
+                waker.wake_by_ref();
+            }
+        }
+    }
+}